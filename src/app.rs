@@ -1,233 +1,763 @@
-use crate::canvas::{CanvasBuffer, ColorbarPos, RgbColor};
+use crate::canvas::{BufColor, CanvasBuffer, ColorbarPos, RgbaColor};
+use crate::history::{History, HistoryLimits};
+use crate::keymap::{Action, Keymap};
 use crate::mode::*;
-use crate::util::{create_window, window_point_to_buffer_point};
+use crate::resize::{ResizeOptions, ResizeTarget};
+use crate::text::{load_font, TextOptions, Typeface};
+use crate::util::{create_window, CharQueue, Point, WindowSizing};
+use crate::viewport::Viewport;
 use minifb::{Key, KeyRepeat, MouseButton, MouseMode, Window};
 use serde::Deserialize;
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::rc::Rc;
+
+/// Shown in eraser mode while the pixels of the image don't line up with the original.
+const ERASER_UNAVAILABLE: &str =
+    "The eraser is off: the image was cropped, resized, flipped or rotated.\nUndo that to erase.";
 
 pub fn edit(initial_buffer: CanvasBuffer, options: EditorOptions) -> CanvasBuffer {
     App::new(initial_buffer, options).run()
 }
 
 #[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct EditorOptions {
     pub brush: Brush,
+    pub highlighter: HighlighterOptions,
     pub mode: Mode,
-    pub colors: Vec<RgbColor>,
-    // TODO: Max version count
-    // TODO: Keybinds?
+    /// Whether the status bar is shown when starting.
+    pub status_bar: bool,
+    pub colors: Vec<RgbaColor>,
+    pub keys: Keymap,
+    pub history: HistoryLimits,
+    pub text: TextOptions,
+    pub bucket: BucketOptions,
+    pub selection: SelectionOptions,
+    pub resize: ResizeOptions,
+    pub redact: RedactOptions,
+    pub window: WindowSizing,
 }
 
 impl Default for EditorOptions {
     fn default() -> Self {
-        let brush = Brush {
-            size: 2,
-            color: RgbColor([0xFF, 0x00, 0x00]),
-        };
+        let brush = Brush::default();
         let mode = Mode::Brush {
-            last_brush_pos: None,
+            path: None,
+            inverted: false,
+            stroke: None,
         };
         let colors = vec![
-            RgbColor([0, 0, 0]),
-            RgbColor([255, 255, 255]),
-            RgbColor([255, 0, 0]),
-            RgbColor([0, 255, 0]),
-            RgbColor([0, 0, 255]),
-            RgbColor([255, 255, 0]),
-            RgbColor([255, 0, 255]),
-            RgbColor([0, 255, 255]),
+            RgbaColor([0, 0, 0, 255]),
+            RgbaColor([255, 255, 255, 255]),
+            RgbaColor([255, 0, 0, 255]),
+            RgbaColor([0, 255, 0, 255]),
+            RgbaColor([0, 0, 255, 255]),
+            RgbaColor([255, 255, 0, 255]),
+            RgbaColor([255, 0, 255, 255]),
+            RgbaColor([0, 255, 255, 255]),
         ];
 
         Self {
             brush,
+            highlighter: HighlighterOptions::default(),
             mode,
+            status_bar: false,
             colors,
+            keys: Keymap::default(),
+            history: HistoryLimits::default(),
+            text: TextOptions::default(),
+            bucket: BucketOptions::default(),
+            selection: SelectionOptions::default(),
+            resize: ResizeOptions::default(),
+            redact: RedactOptions::default(),
+            window: WindowSizing::default(),
         }
     }
 }
 
+/// Size typed by the user to resize the image to.
+#[derive(Default)]
+struct ResizePrompt {
+    input: String,
+    /// Why the last submitted size was rejected.
+    error: Option<&'static str>,
+}
+
 struct App {
     window: Window,
     mode: Mode,
     brush: Brush,
-    colors: Vec<RgbColor>,
+    highlighter_options: HighlighterOptions,
+    colors: Vec<RgbaColor>,
     selected_color: u32,
+    keymap: Keymap,
+    text_options: TextOptions,
+    /// Loaded when text mode is first entered.
+    typeface: Option<Typeface>,
+    bucket_options: BucketOptions,
+    selection_options: SelectionOptions,
+    resize_options: ResizeOptions,
+    resize_prompt: Option<ResizePrompt>,
+    redact_options: RedactOptions,
+    typed_chars: Rc<RefCell<Vec<char>>>,
+    last_mouse_down: bool,
+    /// Position of the mouse in the window during the last frame, to pan the view with.
+    last_window_mouse_pos: (f32, f32),
+    viewport: Viewport,
+    status_bar: bool,
+    /// Message shown at the top of the window until the next click or mode switch.
+    notice: Option<String>,
     temporal_buffer: CanvasBuffer,
-    versions: Vec<CanvasBuffer>,
-    version_index: usize,
+    /// The image as it was loaded, which the eraser paints back.
+    original: CanvasBuffer,
+    /// Pixels copied or cut from a selection.
+    clipboard: Option<CanvasBuffer>,
+    history: History,
+    window_sizing: WindowSizing,
+    /// Size of the image the window was last fit to.
+    image_size: (u32, u32),
+    shown_title: String,
 }
 
 impl App {
     fn new(initial_buffer: CanvasBuffer, options: EditorOptions) -> Self {
         let temporal_buffer = initial_buffer.clone();
-        let versions = vec![initial_buffer.clone()];
-        let version_index = 0;
 
-        let window = create_window(
-            initial_buffer.width() as usize,
-            initial_buffer.height() as usize,
-        );
+        let image_size = (initial_buffer.width(), initial_buffer.height());
+        let window_size = options.window.window_size(image_size);
+        let mut window = create_window(window_size.0 as usize, window_size.1 as usize);
+        let typed_chars = Rc::new(RefCell::new(Vec::new()));
+        window.set_input_callback(Box::new(CharQueue(typed_chars.clone())));
 
-        Self {
+        let viewport = Viewport::new(window_size, image_size);
+
+        let mut app = Self {
             window,
-            mode: options.mode,
+            mode: Mode::View,
             brush: options.brush,
+            highlighter_options: options.highlighter,
 
             colors: options.colors,
             selected_color: 0,
+            keymap: options.keys,
+
+            text_options: options.text,
+            typeface: None,
+            bucket_options: options.bucket,
+            selection_options: options.selection,
+            resize_options: options.resize,
+            resize_prompt: None,
+            redact_options: options.redact,
+            typed_chars,
+            last_mouse_down: false,
+            last_window_mouse_pos: (0.0, 0.0),
+            viewport,
+            status_bar: options.status_bar,
+            notice: None,
 
             temporal_buffer,
-            versions,
-            version_index,
-        }
+            original: initial_buffer.clone(),
+            clipboard: None,
+            window_sizing: options.window,
+            image_size,
+            history: History::new(initial_buffer, options.history),
+            shown_title: String::new(),
+        };
+        app.set_mode(options.mode);
+        app
     }
     fn run(mut self) -> CanvasBuffer {
-        while self.window.is_open() && !self.window.is_key_down(Key::Q) {
-            let mut composite_buffer = self.temporal_buffer.clone();
+        while self.window.is_open() {
+            let mut preview = Preview {
+                image: self.temporal_buffer.clone(),
+                overlays: Vec::new(),
+            };
 
             let window_size = self.window.get_size();
-            let mouse_pos = self
+            let window_size = (window_size.0 as u32, window_size.1 as u32);
+            let image_size = (self.temporal_buffer.width(), self.temporal_buffer.height());
+            self.viewport.resize(window_size, image_size);
+
+            let window_mouse_pos = self
                 .window
                 .get_unscaled_mouse_pos(MouseMode::Pass)
-                .map(|(x, y)| {
-                    window_point_to_buffer_point(
-                        x as i32,
-                        y as i32,
-                        window_size.0 as i32,
-                        window_size.1 as i32,
-                        composite_buffer.width() as i32,
-                        composite_buffer.height() as i32,
-                    )
-                })
-                .unwrap();
-            let mouse_down = self.window.get_mouse_down(MouseButton::Left);
+                .unwrap_or(self.last_window_mouse_pos);
             let scroll = self
                 .window
                 .get_scroll_wheel()
                 .map_or(0, |(_, y)| (y as i32).signum());
+            let ctrl =
+                self.window.is_key_down(Key::LeftCtrl) || self.window.is_key_down(Key::RightCtrl);
+
+            let chars: Vec<char> = self.typed_chars.borrow_mut().drain(..).collect();
+
+            // While a prompt is open, it takes the input instead of the mode and key bindings
+            let prompting = self.resize_prompt.is_some();
+            if let Some(prompt) = self.resize_prompt.take() {
+                self.resize_prompt = self.update_resize_prompt(prompt, &chars);
+            }
+            let captures_keyboard = prompting || self.mode.captures_keyboard();
+
+            // Ctrl+scroll zooms, and dragging with the middle button or with Space held pans.
+            // Neither reaches the mode.
+            if ctrl && scroll != 0 {
+                self.viewport.zoom_by(scroll, window_mouse_pos);
+            }
+            let space_down = !captures_keyboard && self.window.is_key_down(Key::Space);
+            let left_down = self.window.get_mouse_down(MouseButton::Left);
+
+            if self.window.get_mouse_down(MouseButton::Middle) || (space_down && left_down) {
+                self.viewport.pan((
+                    window_mouse_pos.0 - self.last_window_mouse_pos.0,
+                    window_mouse_pos.1 - self.last_window_mouse_pos.1,
+                ));
+            }
+            self.last_window_mouse_pos = window_mouse_pos;
+
+            let mouse_pos = self.viewport.to_image(window_mouse_pos);
+            let mouse_down = left_down && !space_down;
+            let mouse_pressed = mouse_down && !self.last_mouse_down;
+            self.last_mouse_down = mouse_down;
+            if mouse_pressed {
+                self.notice = None;
+            }
 
             let input = Input {
                 mouse_pos,
                 mouse_down,
-                scroll,
+                mouse_pressed,
+                scroll: if ctrl { 0 } else { scroll },
+                shift: self.window.is_key_down(Key::LeftShift)
+                    || self.window.is_key_down(Key::RightShift),
+                ctrl,
+                chars: &chars,
+                zoom: self.viewport.zoom(),
                 window: &self.window,
             };
 
             // Differ behaviour based on current mode
-            let should_save = match self.mode {
-                Mode::Brush {
-                    ref mut last_brush_pos,
-                } => brush_mode(
-                    &input,
-                    &mut self.brush,
-                    last_brush_pos,
-                    &mut self.temporal_buffer,
-                    &mut composite_buffer,
-                ),
-                Mode::Crop { ref mut selection } => crop_mode(
-                    &input,
-                    selection,
-                    &mut self.temporal_buffer,
-                    &mut composite_buffer,
-                ),
-                Mode::View => ShouldSave::Continue,
+            let aligned_with_original = self.history.aligned_with_initial();
+            let should_save = if prompting {
+                ShouldSave::Continue
+            } else {
+                match self.mode {
+                    Mode::Brush {
+                        ref mut path,
+                        inverted,
+                        ref mut stroke,
+                    } => brush_mode(
+                        &input,
+                        &mut self.brush,
+                        path,
+                        inverted,
+                        stroke,
+                        &mut self.temporal_buffer,
+                        &mut preview,
+                    ),
+                    Mode::Highlighter {
+                        ref mut path,
+                        ref mut stroke,
+                    } => highlighter_mode(
+                        &input,
+                        &self.brush,
+                        &mut self.highlighter_options,
+                        path,
+                        stroke,
+                        &mut self.temporal_buffer,
+                        &mut preview,
+                    ),
+                    Mode::Eraser {
+                        ref mut path,
+                        ref mut stroke,
+                    } => eraser_mode(
+                        &input,
+                        &mut self.brush,
+                        Some(&self.original).filter(|_| aligned_with_original),
+                        path,
+                        stroke,
+                        &mut self.temporal_buffer,
+                        &mut preview,
+                    ),
+                    Mode::Text {
+                        ref mut text,
+                        ref mut pos,
+                    } => match self.typeface {
+                        Some(ref mut typeface) => text_mode(
+                            &input,
+                            typeface,
+                            &self.brush,
+                            text,
+                            pos,
+                            &mut self.temporal_buffer,
+                            &mut preview,
+                        ),
+                        None => ShouldSave::Continue,
+                    },
+                    Mode::Arrow { ref mut tail } => arrow_mode(
+                        &input,
+                        &mut self.brush,
+                        tail,
+                        &mut self.temporal_buffer,
+                        &mut preview,
+                    ),
+                    Mode::Shape {
+                        shape,
+                        filled,
+                        ref mut start,
+                    } => shape_mode(
+                        &input,
+                        &mut self.brush,
+                        shape,
+                        filled,
+                        start,
+                        &mut self.temporal_buffer,
+                        &mut preview,
+                    ),
+                    Mode::Bucket => bucket_mode(
+                        &input,
+                        &self.brush,
+                        &self.bucket_options,
+                        &mut self.temporal_buffer,
+                    ),
+                    Mode::Picker => picker_mode(
+                        &input,
+                        &mut self.brush,
+                        &mut self.colors,
+                        self.selected_color,
+                        &self.temporal_buffer,
+                        &mut preview,
+                    ),
+                    Mode::Redact {
+                        ref mut start,
+                        ref mut cached,
+                    } => redact_mode(
+                        &input,
+                        &self.brush,
+                        &mut self.redact_options,
+                        start,
+                        cached,
+                        &mut self.temporal_buffer,
+                        &mut preview,
+                    ),
+                    Mode::Select { ref mut selection } => select_mode(
+                        &input,
+                        &self.selection_options,
+                        selection,
+                        &mut self.temporal_buffer,
+                        &mut preview,
+                    ),
+                    Mode::Crop { ref mut selection } => {
+                        crop_mode(&input, selection, &mut self.temporal_buffer, &mut preview)
+                    }
+                    Mode::View => ShouldSave::Continue,
+                }
             };
 
             // Canvas buffer versioning
             if should_save == ShouldSave::Save {
-                // Save new version.
-                self.version_index += 1;
-
-                // Delete undone versions that still exist.
-                if self.version_index < self.versions.len() {
-                    self.versions.truncate(self.version_index);
-                    self.versions.shrink_to_fit();
-                }
-
-                self.versions.push(self.temporal_buffer.clone());
+                self.history.save(&self.temporal_buffer);
             }
 
             // Keymaps
-            let keys = self.window.get_keys_pressed(KeyRepeat::No);
-            let ctrl = self.window.is_key_down(Key::LeftCtrl);
-            let shift = self.window.is_key_down(Key::LeftShift);
-            let z = self.window.is_key_pressed(Key::Z, KeyRepeat::Yes);
+            let mut color_change = None;
 
-            if ctrl && z {
-                let add_version = |i: i32| {
-                    let i = self.version_index as i32 + i;
+            let window_center = (window_size.0 as f32 / 2.0, window_size.1 as f32 / 2.0);
+            let actions = if captures_keyboard {
+                Vec::new()
+            } else {
+                self.keymap.pressed_actions(&self.window)
+            };
 
-                    if i < 0 {
-                        return 0;
+            for action in actions {
+                match action {
+                    Action::Quit => return self.finish(),
+                    Action::Undo => {
+                        // Undoing drops the operation in progress before going back a version
+                        if !self.mode.cancel_pending(&mut self.temporal_buffer)
+                            && self.history.undo()
+                        {
+                            self.restore_version();
+                        }
                     }
-                    if i >= self.versions.len() as i32 {
-                        return self.versions.len() - 1;
+                    Action::Redo => {
+                        if self.history.redo() {
+                            self.restore_version();
+                        }
                     }
+                    Action::Cancel => {
+                        if !self.mode.cancel_pending(&mut self.temporal_buffer) {
+                            self.set_mode(Mode::View);
+                        }
+                    }
+                    Action::Copy => {
+                        if let Mode::Select {
+                            selection: Some(ref selection),
+                        } = self.mode
+                        {
+                            let filter = self.selection_options.filter;
+                            if let Some(pixels) = selection.copy(filter, &self.temporal_buffer) {
+                                self.clipboard = Some(pixels);
+                            }
+                        }
+                    }
+                    Action::Cut => {
+                        if let Mode::Select {
+                            selection: Some(ref mut selection),
+                        } = self.mode
+                        {
+                            let filter = self.selection_options.filter;
+                            if let Some(pixels) = selection.copy(filter, &self.temporal_buffer) {
+                                // Lifting leaves the hole the cut pixels are taken from
+                                selection.lift(&mut self.temporal_buffer);
+                                self.clipboard = Some(pixels);
+                                self.mode = Mode::Select { selection: None };
+                                self.history.save(&self.temporal_buffer);
+                            }
+                        }
+                    }
+                    Action::Paste => {
+                        if let Some(pixels) = self.clipboard.clone() {
+                            self.set_mode(Mode::Select { selection: None });
 
-                    i as usize
-                };
+                            let in_bounds = mouse_pos.0 >= 0
+                                && mouse_pos.1 >= 0
+                                && self
+                                    .temporal_buffer
+                                    .in_bounds(mouse_pos.0 as u32, mouse_pos.1 as u32);
 
-                if !shift {
-                    self.version_index = add_version(-1); // Undo
-                } else {
-                    self.version_index = add_version(1); // Redo
+                            let pos = if in_bounds { mouse_pos } else { (0, 0) };
+                            self.mode = Mode::Select {
+                                selection: Some(Selection::Floating(Floating::new(
+                                    pixels, pos, None,
+                                ))),
+                            };
+                        }
+                    }
+                    Action::FlipHorizontal => {
+                        self.transform(CanvasBuffer::flip_horizontal, Floating::flip_horizontally);
+                    }
+                    Action::FlipVertical => {
+                        self.transform(CanvasBuffer::flip_vertical, Floating::flip_vertically);
+                    }
+                    Action::RotateCw => {
+                        self.transform(CanvasBuffer::rotate_90, |floating| {
+                            floating.rotate(FRAC_PI_2);
+                        });
+                    }
+                    Action::RotateCcw => {
+                        self.transform(CanvasBuffer::rotate_270, |floating| {
+                            floating.rotate(-FRAC_PI_2);
+                        });
+                    }
+                    Action::Rotate180 => {
+                        self.transform(CanvasBuffer::rotate_180, |floating| floating.rotate(PI));
+                    }
+                    Action::Resize => {
+                        self.settle_pending();
+                        self.resize_prompt = Some(ResizePrompt::default());
+                    }
+                    Action::Enter(name) => {
+                        self.set_mode(name.parse().expect("action names a known mode"))
+                    }
+                    Action::SelectColor(i) => color_change = Some(i),
+                    Action::ZoomIn => self.viewport.zoom_by(1, window_center),
+                    Action::ZoomOut => self.viewport.zoom_by(-1, window_center),
+                    Action::ZoomFit => self.viewport.zoom_to(None, window_center),
+                    Action::Zoom(percent) => {
+                        self.viewport
+                            .zoom_to(Some(percent as f32 / 100.0), window_center);
+                    }
+                    Action::ToggleStatusBar => self.status_bar = !self.status_bar,
+                    Action::ToggleVariant => match self.mode {
+                        Mode::Shape { ref mut filled, .. } => *filled = !*filled,
+                        Mode::Brush {
+                            ref mut inverted, ..
+                        } => *inverted = !*inverted,
+                        Mode::Redact { .. } => {
+                            self.redact_options.style = self.redact_options.style.next();
+                        }
+                        _ => (),
+                    },
                 }
+            }
 
-                self.temporal_buffer = self.versions[self.version_index].clone();
+            if let Some(new_color) = color_change.map(|c| c.clamp(0, self.colors.len() as u32 - 1))
+            {
+                self.selected_color = new_color;
+                self.brush.color = self.colors[new_color as usize];
             }
 
-            let mut color_change = None;
+            // minifb can't resize windows, so make a new one when the aspect ratio changes
+            let image_size = (self.temporal_buffer.width(), self.temporal_buffer.height());
+            if image_size != self.image_size {
+                let (old_w, old_h) = (u64::from(self.image_size.0), u64::from(self.image_size.1));
 
-            if let Some(keys) = keys {
-                for key in keys {
-                    match key {
-                        // Mode switching
-                        Key::Escape => self.mode = Mode::View,
-                        Key::B => {
-                            self.mode = Mode::Brush {
-                                last_brush_pos: None,
-                            }
-                        }
-                        Key::C => self.mode = Mode::Crop { selection: None },
-                        // Color switching
-                        Key::Key1 => color_change = Some(0),
-                        Key::Key2 => color_change = Some(1),
-                        Key::Key3 => color_change = Some(2),
-                        Key::Key4 => color_change = Some(3),
-                        Key::Key5 => color_change = Some(4),
-                        Key::Key6 => color_change = Some(5),
-                        Key::Key7 => color_change = Some(6),
-                        Key::Key8 => color_change = Some(7),
-                        Key::Key9 => color_change = Some(8),
-                        _ => (),
-                    }
+                if old_w * u64::from(image_size.1) != old_h * u64::from(image_size.0) {
+                    self.recreate_window();
                 }
+                self.image_size = image_size;
             }
 
-            if let Some(new_color) = color_change.map(|c| c.clamp(0, self.colors.len() as u32 - 1)) {
-                self.selected_color = new_color;
-                self.brush.color = self.colors[new_color as usize];
+            // Show how far back the user can go
+            let title = format!(
+                "canved - {} undo, {} redo",
+                self.history.undo_depth(),
+                self.history.redo_depth()
+            );
+            if title != self.shown_title {
+                self.window.set_title(&title);
+                self.shown_title = title;
             }
 
             // Colorbar
             let draw_colorbar = match self.mode {
                 Mode::Brush { .. } => true,
+                Mode::Highlighter { .. } => true,
+                Mode::Eraser { .. } => false,
+                Mode::Text { .. } => true,
+                Mode::Arrow { .. } => true,
+                Mode::Shape { .. } => true,
+                Mode::Bucket => true,
+                Mode::Picker => true,
+                Mode::Redact { .. } => true,
+                Mode::Select { .. } => false,
                 Mode::Crop { .. } => false,
                 Mode::View => false,
             };
 
+            // The image is shown through the viewport, and the overlays of the mode and the UI
+            // drawn over it at the size of the window
+            let (frame_width, frame_height) = (window_size.0.max(1), window_size.1.max(1));
+            let mut frame = CanvasBuffer::new(
+                vec![BufColor::TRANSPARENT; (frame_width * frame_height) as usize],
+                frame_width,
+                frame_height,
+            );
+            self.viewport.render(&preview.image, &mut frame);
+            for overlay in &preview.overlays {
+                overlay.draw(&mut frame, &self.viewport, &preview.image);
+            }
+
             if draw_colorbar {
-                composite_buffer.draw_colorbar(&self.colors, self.selected_color, ColorbarPos::Top);
+                frame.draw_colorbar(&self.colors, self.selected_color, ColorbarPos::Top);
+            }
+            if self.status_bar {
+                frame.draw_status_bar(&self.status(mouse_pos));
+            }
+            if let Some(ref notice) = self.notice {
+                frame.draw_notice(notice);
+            } else if let Mode::Eraser { .. } = self.mode {
+                if !self.history.aligned_with_initial() {
+                    frame.draw_notice(ERASER_UNAVAILABLE);
+                }
+            }
+            if let Some(ref prompt) = self.resize_prompt {
+                let mut text = format!(
+                    "Resize {}x{} to: {}_",
+                    self.temporal_buffer.width(),
+                    self.temporal_buffer.height(),
+                    prompt.input
+                );
+                if let Some(error) = prompt.error {
+                    text.push('\n');
+                    text.push_str(error);
+                }
+
+                frame.draw_prompt(&text);
             }
 
+            frame.flatten_onto_checkerboard();
+
             self.window
                 .update_with_buffer(
-                    unsafe { composite_buffer.raw_data() },
-                    composite_buffer.width() as usize,
-                    composite_buffer.height() as usize,
+                    unsafe { frame.raw_data() },
+                    frame.width() as usize,
+                    frame.height() as usize,
                 )
                 .unwrap();
         }
 
+        self.finish()
+    }
+
+    /// Commits any operation in progress and returns the edited image.
+    fn finish(mut self) -> CanvasBuffer {
+        self.mode.commit_pending(
+            &self.brush,
+            self.typeface.as_ref(),
+            &self.selection_options,
+            &mut self.temporal_buffer,
+        );
         self.temporal_buffer
     }
+
+    /// Applies an operation to the selected pixels if there are any, or else to the whole
+    /// image, saving the result. The operation in progress is committed first, since it
+    /// wouldn't match the transformed image.
+    fn transform(&mut self, image_op: fn(&mut CanvasBuffer), selection_op: fn(&mut Floating)) {
+        if let Mode::Select {
+            selection: Some(ref mut selection),
+        } = self.mode
+        {
+            if let Some(floating) = selection.lift(&mut self.temporal_buffer) {
+                selection_op(floating);
+                return;
+            }
+        }
+
+        self.settle_pending();
+        image_op(&mut self.temporal_buffer);
+        self.history.save_moved(&self.temporal_buffer);
+    }
+
+    /// Describes the mode, the pixel under the cursor, the image and the view, for the
+    /// status bar.
+    fn status(&self, mouse_pos: Point) -> String {
+        let (width, height) = (self.temporal_buffer.width(), self.temporal_buffer.height());
+
+        let pixel = match (u32::try_from(mouse_pos.0), u32::try_from(mouse_pos.1)) {
+            (Ok(x), Ok(y)) if self.temporal_buffer.in_bounds(x, y) => {
+                let RgbaColor([r, g, b, a]) = self.temporal_buffer.get_pixel(x, y).into();
+                format!("{}, {}  #{:02X}{:02X}{:02X}{:02X}", x, y, r, g, b, a)
+            }
+            _ => String::from("-"),
+        };
+
+        let size = match self.mode {
+            Mode::Brush { .. } | Mode::Eraser { .. } | Mode::Arrow { .. } | Mode::Shape { .. } => {
+                Some(self.brush.size.to_string())
+            }
+            Mode::Highlighter { .. } => Some(self.highlighter_options.size.to_string()),
+            Mode::Text { .. } => self
+                .typeface
+                .as_ref()
+                .map(|typeface| typeface.size.to_string()),
+            _ => None,
+        };
+
+        let mut parts = vec![
+            self.mode.name().to_string(),
+            pixel,
+            format!("{}x{}", width, height),
+            format!("{}%", (self.viewport.zoom() * 100.0).round()),
+        ];
+        parts.extend(size.map(|size| format!("size {}", size)));
+        parts.join("  ")
+    }
+
+    /// Replaces the window with one sized for the current image.
+    fn recreate_window(&mut self) {
+        let image_size = (self.temporal_buffer.width(), self.temporal_buffer.height());
+        let (width, height) = self.window_sizing.window_size(image_size);
+        self.window = create_window(width as usize, height as usize);
+        self.window
+            .set_input_callback(Box::new(CharQueue(self.typed_chars.clone())));
+        self.shown_title.clear();
+    }
+
+    /// Replaces the image with the current version, dropping any floating pixels.
+    fn restore_version(&mut self) {
+        if let Mode::Select { ref mut selection } = self.mode {
+            *selection = None;
+        }
+        self.temporal_buffer = self.history.current().clone();
+    }
+
+    /// Edits the resize prompt with the typed characters, resizing the image when Enter is
+    /// pressed. Returns the prompt if it stays open: Escape or a valid size close it.
+    fn update_resize_prompt(
+        &mut self,
+        mut prompt: ResizePrompt,
+        chars: &[char],
+    ) -> Option<ResizePrompt> {
+        if self.window.is_key_pressed(Key::Escape, KeyRepeat::No) {
+            return None;
+        }
+
+        let erase = self.window.is_key_pressed(Key::Backspace, KeyRepeat::Yes);
+        if erase || !chars.is_empty() {
+            prompt.error = None;
+        }
+        prompt
+            .input
+            .extend(chars.iter().filter(|c| !c.is_control()));
+        if erase {
+            prompt.input.pop();
+        }
+
+        if !self.window.is_key_pressed(Key::Enter, KeyRepeat::No) {
+            return Some(prompt);
+        }
+
+        let (width, height) = (self.temporal_buffer.width(), self.temporal_buffer.height());
+        let size = prompt
+            .input
+            .parse::<ResizeTarget>()
+            .and_then(|target| target.size(width, height));
+
+        match size {
+            Ok(size) => {
+                if size != (width, height) {
+                    self.temporal_buffer =
+                        self.temporal_buffer
+                            .resized(size.0, size.1, self.resize_options.filter);
+                    self.history.save(&self.temporal_buffer);
+                }
+                None
+            }
+            Err(e) => {
+                prompt.error = Some(e);
+                Some(prompt)
+            }
+        }
+    }
+
+    /// Finishes the operation in progress of the mode, saving it to the history.
+    fn commit_pending(&mut self) {
+        let should_save = self.mode.commit_pending(
+            &self.brush,
+            self.typeface.as_ref(),
+            &self.selection_options,
+            &mut self.temporal_buffer,
+        );
+        if should_save == ShouldSave::Save {
+            self.history.save(&self.temporal_buffer);
+        }
+    }
+
+    /// Commits what can be committed of the operation in progress and forgets the rest, so
+    /// that nothing is left pointing at the image before its geometry changes.
+    fn settle_pending(&mut self) {
+        self.commit_pending();
+        self.mode.reset();
+    }
+
+    /// Switches mode, committing any operation in progress. Text mode is only entered if a
+    /// font can be loaded, or else the error is shown in the window.
+    fn set_mode(&mut self, mode: Mode) {
+        self.commit_pending();
+        self.notice = None;
+
+        if let Mode::Text { .. } = mode {
+            if self.typeface.is_none() {
+                match load_font(self.text_options.font.as_deref()) {
+                    Ok(font) => {
+                        self.typeface = Some(Typeface {
+                            font,
+                            size: self.text_options.size,
+                        });
+                    }
+                    Err(e) => {
+                        eprintln!("canved: {}", e);
+                        self.notice = Some(e);
+                        return;
+                    }
+                }
+            }
+        }
+
+        self.mode = mode;
+    }
 }
@@ -1,29 +1,121 @@
+use crate::font::{glyph, label_size, GLYPH_HEIGHT, GLYPH_WIDTH};
 use crate::util::Point;
+use serde::de::{self, Deserializer};
 use serde::Deserialize;
 
+/// A color packed as `0xAARRGGBB`. The window ignores the alpha byte, so transparent
+/// regions must be flattened before being displayed.
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct BufColor(pub u32);
 
-impl From<RgbColor> for BufColor {
-    fn from(rgb: RgbColor) -> BufColor {
-        let (r, g, b) = (rgb.0[0] as u32, rgb.0[1] as u32, rgb.0[2] as u32);
-        BufColor((r << 16) | (g << 8) | b)
+impl BufColor {
+    pub const TRANSPARENT: BufColor = BufColor(0);
+    pub const BLACK: BufColor = BufColor(0xFF000000);
+
+    pub fn alpha(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub fn with_alpha(self, alpha: u8) -> BufColor {
+        BufColor((self.0 & 0x00FFFFFF) | ((alpha as u32) << 24))
+    }
+
+    /// Inverts the color channels, keeping the alpha.
+    pub fn inverted(self) -> BufColor {
+        BufColor(self.0 ^ 0x00FFFFFF)
+    }
+
+    /// Interpolates every channel between this color and `other`, with `t` between 0 and 1.
+    pub fn mix(self, other: BufColor, t: f32) -> BufColor {
+        let (a, b) = (self.0.to_be_bytes(), other.0.to_be_bytes());
+        let mut mixed = [0; 4];
+
+        for (m, (a, b)) in mixed.iter_mut().zip(a.iter().zip(&b)) {
+            *m = (*a as f32 + (*b as f32 - *a as f32) * t).round() as u8;
+        }
+
+        BufColor(u32::from_be_bytes(mixed))
+    }
+
+    /// Multiplies the color channels by those of `other`, which darkens them like ink on
+    /// paper. The alpha is kept.
+    pub fn multiply(self, other: BufColor) -> BufColor {
+        let [a, r, g, b] = self.0.to_be_bytes();
+        let [_, other_r, other_g, other_b] = other.0.to_be_bytes();
+        let mul = |c: u8, other: u8| (c as u32 * other as u32 / 0xFF) as u8;
+
+        BufColor(u32::from_be_bytes([
+            a,
+            mul(r, other_r),
+            mul(g, other_g),
+            mul(b, other_b),
+        ]))
+    }
+
+    /// Composites this color over `bg`.
+    pub fn over(self, bg: BufColor) -> BufColor {
+        let a = self.alpha() as u32;
+
+        match a {
+            0xFF => self,
+            0 => bg,
+            _ => {
+                let [_, r, g, b] = self.0.to_be_bytes();
+                let [bg_a, bg_r, bg_g, bg_b] = bg.0.to_be_bytes();
+                let out_a = a + bg_a as u32 * (0xFF - a) / 0xFF;
+
+                if out_a == 0 {
+                    return BufColor::TRANSPARENT;
+                }
+
+                let mix = |fg: u8, bg: u8| {
+                    let fg = fg as u32 * a;
+                    let bg = bg as u32 * bg_a as u32 * (0xFF - a) / 0xFF;
+                    ((fg + bg) / out_a) as u8
+                };
+
+                RgbaColor([mix(r, bg_r), mix(g, bg_g), mix(b, bg_b), out_a as u8]).into()
+            }
+        }
+    }
+}
+
+impl From<RgbaColor> for BufColor {
+    fn from(rgba: RgbaColor) -> BufColor {
+        let [r, g, b, a] = rgba.0;
+        BufColor(u32::from_be_bytes([a, r, g, b]))
     }
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Debug)]
-pub struct RgbColor(pub [u8; 3]);
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RgbaColor(pub [u8; 4]);
+
+impl From<BufColor> for RgbaColor {
+    fn from(color: BufColor) -> RgbaColor {
+        let [a, r, g, b] = color.0.to_be_bytes();
+        RgbaColor([r, g, b, a])
+    }
+}
+
+/// Colors are written as `[r, g, b]` or `[r, g, b, a]`. The alpha defaults to opaque.
+impl<'de> Deserialize<'de> for RgbaColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let channels = Vec::<u8>::deserialize(deserializer)?;
 
-impl From<BufColor> for RgbColor {
-    fn from(color: BufColor) -> RgbColor {
-        let [_, r, g, b] = color.0.to_be_bytes();
-        RgbColor([r, g, b])
+        match *channels.as_slice() {
+            [r, g, b] => Ok(RgbaColor([r, g, b, 0xFF])),
+            [r, g, b, a] => Ok(RgbaColor([r, g, b, a])),
+            _ => Err(de::Error::invalid_length(
+                channels.len(),
+                &"3 or 4 color channels",
+            )),
+        }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct CanvasBuffer {
     data: Vec<BufColor>,
     width: u32,
@@ -80,7 +172,7 @@ impl CanvasBuffer {
 
     /// Crops the image. Returns true if the crop was succesful.
     pub fn crop(&mut self, x: u32, y: u32, w: u32, h: u32) -> bool {
-        let mut new_buf = vec![BufColor(0); w as usize * h as usize];
+        let mut new_buf = vec![BufColor::TRANSPARENT; w as usize * h as usize];
 
         if !self.in_bounds(x, y) || !self.in_bounds(x + w, y + h) {
             return false;
@@ -99,6 +191,303 @@ impl CanvasBuffer {
         self.height = h;
         true
     }
+
+    /// Rotates the image a quarter turn clockwise, swapping its width and height.
+    pub fn rotate_90(&mut self) {
+        let mut data = Vec::with_capacity(self.data.len());
+
+        for y in 0..self.width {
+            for x in 0..self.height {
+                data.push(self.get_pixel(y, self.height - 1 - x));
+            }
+        }
+
+        self.data = data;
+        std::mem::swap(&mut self.width, &mut self.height);
+    }
+
+    /// Rotates the image half a turn.
+    pub fn rotate_180(&mut self) {
+        self.data.reverse();
+    }
+
+    /// Rotates the image a quarter turn counterclockwise, swapping its width and height.
+    pub fn rotate_270(&mut self) {
+        let mut data = Vec::with_capacity(self.data.len());
+
+        for y in 0..self.width {
+            for x in 0..self.height {
+                data.push(self.get_pixel(self.width - 1 - y, x));
+            }
+        }
+
+        self.data = data;
+        std::mem::swap(&mut self.width, &mut self.height);
+    }
+
+    /// Mirrors the image along its vertical axis.
+    pub fn flip_horizontal(&mut self) {
+        for row in self.data.chunks_mut(self.width as usize) {
+            row.reverse();
+        }
+    }
+
+    /// Mirrors the image along its horizontal axis.
+    pub fn flip_vertical(&mut self) {
+        let w = self.width as usize;
+        let h = self.height as usize;
+
+        for y in 0..h / 2 {
+            let (top, bottom) = self.data.split_at_mut((h - 1 - y) * w);
+            top[y * w..(y + 1) * w].swap_with_slice(&mut bottom[..w]);
+        }
+    }
+
+    /// Fills the contiguous region around the given pixel with `color`. Pixels belong to the
+    /// region if none of their channels differ from the starting pixel by more than
+    /// `tolerance`. With `diagonal`, pixels touching only by their corners are contiguous.
+    pub fn flood_fill(&mut self, x: u32, y: u32, color: BufColor, tolerance: u8, diagonal: bool) {
+        let target = self.get_pixel(x, y).0.to_be_bytes();
+        let matches = |c: BufColor| {
+            c.0.to_be_bytes()
+                .iter()
+                .zip(&target)
+                .all(|(a, b)| (*a as i32 - *b as i32).abs() <= tolerance as i32)
+        };
+
+        let (w, h) = (self.width, self.height);
+        let spread = diagonal as u32;
+        let mut filled = vec![false; self.data.len()];
+        let mut stack = vec![(x, y)];
+
+        while let Some((x, y)) = stack.pop() {
+            let index = self.index(x, y);
+            if filled[index] || !matches(self.data[index]) {
+                continue;
+            }
+
+            // Extend the span to the left and right as far as possible
+            let fillable = |x, data: &[BufColor], filled: &[bool]| {
+                let index = (y * w + x) as usize;
+                !filled[index] && matches(data[index])
+            };
+            let mut x_min = x;
+            while x_min > 0 && fillable(x_min - 1, &self.data, &filled) {
+                x_min -= 1;
+            }
+            let mut x_max = x;
+            while x_max + 1 < w && fillable(x_max + 1, &self.data, &filled) {
+                x_max += 1;
+            }
+
+            for x in x_min..=x_max {
+                let index = self.index(x, y);
+                self.data[index] = color;
+                filled[index] = true;
+            }
+
+            // Queue the start of every fillable span in the rows above and below
+            let rows = [y.checked_sub(1), Some(y + 1).filter(|y| *y < h)];
+            for ny in rows.iter().flatten() {
+                let mut in_span = false;
+
+                for x in x_min.saturating_sub(spread)..=(x_max + spread).min(w - 1) {
+                    let index = self.index(x, *ny);
+                    let fillable = !filled[index] && matches(self.data[index]);
+
+                    if fillable && !in_span {
+                        stack.push((x, *ny));
+                    }
+                    in_span = fillable;
+                }
+            }
+        }
+    }
+
+    /// Copies a rectangle of the image into a new buffer. The rectangle must be in bounds.
+    pub fn copy_rect(&self, x: u32, y: u32, w: u32, h: u32) -> CanvasBuffer {
+        let mut data = Vec::with_capacity(w as usize * h as usize);
+
+        for j in y..y + h {
+            let start = self.index(x, j);
+            data.extend_from_slice(&self.data[start..start + w as usize]);
+        }
+
+        CanvasBuffer::new(data, w, h)
+    }
+
+    /// Overwrites the pixels at the given position with the contents of `other`. The
+    /// pasted buffer must fit inside this one.
+    pub fn paste(&mut self, x: u32, y: u32, other: &CanvasBuffer) {
+        for j in 0..other.height {
+            let start = self.index(x, y + j);
+            let other_start = other.index(0, j);
+
+            self.data[start..start + other.width as usize]
+                .copy_from_slice(&other.data[other_start..other_start + other.width as usize]);
+        }
+    }
+
+    /// Returns the bounding rectangle `(x, y, w, h)` of the pixels that differ between two
+    /// buffers of the same size, or `None` if they are equal.
+    pub fn diff_bounds(&self, other: &CanvasBuffer) -> Option<(u32, u32, u32, u32)> {
+        let mut bounds: Option<(u32, u32, u32, u32)> = None;
+
+        for (y, (row, other_row)) in self
+            .data
+            .chunks(self.width as usize)
+            .zip(other.data.chunks(other.width as usize))
+            .enumerate()
+        {
+            if row == other_row {
+                continue;
+            }
+
+            let first = row.iter().zip(other_row).position(|(a, b)| a != b);
+            let last = row.iter().zip(other_row).rposition(|(a, b)| a != b);
+
+            if let (Some(first), Some(last)) = (first, last) {
+                let (first, last, y) = (first as u32, last as u32, y as u32);
+
+                bounds = Some(match bounds {
+                    Some((x_min, y_min, x_max, _)) => (x_min.min(first), y_min, x_max.max(last), y),
+                    None => (first, y, last, y),
+                });
+            }
+        }
+
+        bounds.map(|(x_min, y_min, x_max, y_max)| {
+            (x_min, y_min, x_max - x_min + 1, y_max - y_min + 1)
+        })
+    }
+}
+
+/// How pixels are interpolated when an image is resampled.
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    /// Lanczos with a radius of 3 pixels. Sharper than bilinear when shrinking images.
+    Lanczos,
+}
+
+impl Filter {
+    /// Radius of the filter kernel, in source pixels.
+    fn support(self) -> f32 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Lanczos => 3.0,
+        }
+    }
+
+    fn kernel(self, x: f32) -> f32 {
+        match self {
+            Filter::Nearest => {
+                if x.abs() <= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Bilinear => (1.0 - x.abs()).max(0.0),
+            Filter::Lanczos => {
+                if x == 0.0 {
+                    1.0
+                } else if x.abs() < 3.0 {
+                    let px = std::f32::consts::PI * x;
+                    3.0 * px.sin() * (px / 3.0).sin() / (px * px)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+/// Converts a color to `[r, g, b, a]` with the color channels multiplied by the alpha, so
+/// that colors can be averaged without transparent pixels bleeding into opaque ones.
+fn premultiplied(color: BufColor) -> [f32; 4] {
+    let RgbaColor([r, g, b, a]) = color.into();
+    let alpha = a as f32 / 255.0;
+    [
+        r as f32 * alpha,
+        g as f32 * alpha,
+        b as f32 * alpha,
+        a as f32,
+    ]
+}
+
+fn from_premultiplied([r, g, b, a]: [f32; 4]) -> BufColor {
+    let a = a.round().clamp(0.0, 255.0);
+
+    if a == 0.0 {
+        return BufColor::TRANSPARENT;
+    }
+
+    let unmultiply = |c: f32| (c * 255.0 / a).round().clamp(0.0, 255.0) as u8;
+    RgbaColor([unmultiply(r), unmultiply(g), unmultiply(b), a as u8]).into()
+}
+
+/// Computes, for each of the `dst_len` pixels of a resampled row or column, the index of
+/// the first source pixel it takes from and the weights of the source pixels from there.
+fn resample_weights(src_len: u32, dst_len: u32, filter: Filter) -> Vec<(usize, Vec<f32>)> {
+    let scale = src_len as f32 / dst_len as f32;
+    // Widen the kernel when shrinking, so that every source pixel is taken into account
+    let stretch = scale.max(1.0);
+    let support = filter.support() * stretch;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(src_len as usize);
+
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| filter.kernel((j as f32 + 0.5 - center) / stretch))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+
+            if sum > 0.0 {
+                weights.iter_mut().for_each(|w| *w /= sum);
+            }
+
+            (start, weights)
+        })
+        .collect()
+}
+
+/// Convolves lines of pixels with a kernel centered on each pixel, clamping to the ends of
+/// the lines. `lines` and `pixels` give the number of lines and of pixels per line, along
+/// with the distance between consecutive ones in the slice.
+fn convolve_lines(
+    src: &[[f32; 4]],
+    kernel: &[f32],
+    lines: (usize, usize),
+    pixels: (usize, usize),
+) -> Vec<[f32; 4]> {
+    let radius = (kernel.len() / 2) as isize;
+    let last = pixels.0 as isize - 1;
+    let mut out = vec![[0.0; 4]; src.len()];
+
+    for line in 0..lines.0 {
+        let start = line * lines.1;
+
+        for i in 0..pixels.0 {
+            let color = &mut out[start + i * pixels.1];
+
+            for (k, weight) in kernel.iter().enumerate() {
+                let j = (i as isize + k as isize - radius).clamp(0, last) as usize;
+
+                for (c, channel) in color.iter_mut().zip(&src[start + j * pixels.1]) {
+                    *c += channel * weight;
+                }
+            }
+        }
+    }
+
+    out
 }
 
 pub enum ColorbarPos {
@@ -124,14 +513,221 @@ impl CanvasBuffer {
             }
         }
     }
+}
+
+// Resampling functions
+impl CanvasBuffer {
+    /// Samples the color at the given point, where the center of pixel (0, 0) is at
+    /// (0.5, 0.5). Points outside of the image take the color of the closest edge.
+    pub fn sample(&self, x: f32, y: f32, filter: Filter) -> BufColor {
+        let (max_x, max_y) = (self.width as i32 - 1, self.height as i32 - 1);
+
+        match filter {
+            Filter::Nearest => {
+                let px = (x.floor() as i32).clamp(0, max_x);
+                let py = (y.floor() as i32).clamp(0, max_y);
+                self.get_pixel(px as u32, py as u32)
+            }
+            filter => {
+                let (x, y) = (x - 0.5, y - 0.5);
+                let support = filter.support();
+                let (x_min, x_max) = ((x - support).ceil() as i32, (x + support).floor() as i32);
+                let (y_min, y_max) = ((y - support).ceil() as i32, (y + support).floor() as i32);
+
+                let mut color = [0.0; 4];
+                let mut total = 0.0;
+
+                for py in y_min..=y_max {
+                    let wy = filter.kernel(py as f32 - y);
+
+                    for px in x_min..=x_max {
+                        let weight = filter.kernel(px as f32 - x) * wy;
+                        let pixel =
+                            self.get_pixel(px.clamp(0, max_x) as u32, py.clamp(0, max_y) as u32);
+
+                        for (c, channel) in color.iter_mut().zip(&premultiplied(pixel)) {
+                            *c += channel * weight;
+                        }
+                        total += weight;
+                    }
+                }
+
+                if total != 0.0 {
+                    color.iter_mut().for_each(|c| *c /= total);
+                }
+
+                from_premultiplied(color)
+            }
+        }
+    }
+
+    /// Returns a copy of the image scaled to the given size.
+    pub fn resized(&self, width: u32, height: u32, filter: Filter) -> CanvasBuffer {
+        if filter == Filter::Nearest {
+            let mut data = Vec::with_capacity(width as usize * height as usize);
+
+            for y in 0..height {
+                let src_y = (y as u64 * 2 + 1) * self.height as u64 / (height as u64 * 2);
+
+                for x in 0..width {
+                    let src_x = (x as u64 * 2 + 1) * self.width as u64 / (width as u64 * 2);
+                    data.push(self.get_pixel(src_x as u32, src_y as u32));
+                }
+            }
+
+            return CanvasBuffer::new(data, width, height);
+        }
+
+        let src: Vec<[f32; 4]> = self.data.iter().map(|c| premultiplied(*c)).collect();
+        let accumulate = |color: &mut [f32; 4], pixel: &[f32; 4], weight: f32| {
+            for (c, channel) in color.iter_mut().zip(pixel) {
+                *c += channel * weight;
+            }
+        };
+
+        // Resample the rows first, then the columns
+        let (src_w, dst_w) = (self.width as usize, width as usize);
+        let row_weights = resample_weights(self.width, width, filter);
+        let mut rows = vec![[0.0; 4]; dst_w * self.height as usize];
+
+        for (src_row, dst_row) in src.chunks(src_w).zip(rows.chunks_mut(dst_w)) {
+            for ((start, weights), color) in row_weights.iter().zip(dst_row) {
+                for (pixel, weight) in src_row[*start..].iter().zip(weights) {
+                    accumulate(color, pixel, *weight);
+                }
+            }
+        }
+
+        let mut data = Vec::with_capacity(dst_w * height as usize);
+
+        for (start, weights) in resample_weights(self.height, height, filter) {
+            for x in 0..dst_w {
+                let mut color = [0.0; 4];
+
+                for (k, weight) in weights.iter().enumerate() {
+                    accumulate(&mut color, &rows[(start + k) * dst_w + x], *weight);
+                }
+
+                data.push(from_premultiplied(color));
+            }
+        }
+
+        CanvasBuffer::new(data, width, height)
+    }
+
+    /// Returns a copy of the image rotated clockwise by `angle` radians around its center.
+    /// The copy is enlarged to fit the whole rotated image, keeping the same center, and
+    /// the area around the image is transparent.
+    pub fn rotated(&self, angle: f32, filter: Filter) -> CanvasBuffer {
+        let (sin, cos) = angle.sin_cos();
+        let (w, h) = (self.width as f32, self.height as f32);
+        let out_w = (w * cos.abs() + h * sin.abs()).round().max(1.0) as u32;
+        let out_h = (w * sin.abs() + h * cos.abs()).round().max(1.0) as u32;
+
+        let mut data = Vec::with_capacity(out_w as usize * out_h as usize);
+
+        for y in 0..out_h {
+            for x in 0..out_w {
+                let dx = x as f32 + 0.5 - out_w as f32 / 2.0;
+                let dy = y as f32 + 0.5 - out_h as f32 / 2.0;
+                let src_x = cos * dx + sin * dy + w / 2.0;
+                let src_y = -sin * dx + cos * dy + h / 2.0;
+
+                // Distance from the point to the closest edge, negative outside the image
+                let inside = src_x.min(w - src_x).min(src_y).min(h - src_y);
+                let coverage = match filter {
+                    Filter::Nearest if inside >= 0.0 => 1.0,
+                    Filter::Nearest => 0.0,
+                    _ => (inside + 0.5).clamp(0.0, 1.0),
+                };
+
+                data.push(if coverage > 0.0 {
+                    let color = self.sample(src_x, src_y, filter);
+                    let alpha = (color.alpha() as f32 * coverage).round() as u8;
+                    color.with_alpha(alpha)
+                } else {
+                    BufColor::TRANSPARENT
+                });
+            }
+        }
+
+        CanvasBuffer::new(data, out_w, out_h)
+    }
+}
+
+// Filter functions
+impl CanvasBuffer {
+    /// Splits the rectangle into `block` sized squares, starting from its top left corner,
+    /// and fills each one with its average color. The rectangle must be in bounds.
+    pub fn pixelate(&mut self, x: u32, y: u32, w: u32, h: u32, block: u32) {
+        let block = block.max(1);
+
+        for block_y in (y..y + h).step_by(block as usize) {
+            for block_x in (x..x + w).step_by(block as usize) {
+                let block_w = block.min(x + w - block_x);
+                let block_h = block.min(y + h - block_y);
+                let mut sum = [0.0; 4];
+
+                for j in block_y..block_y + block_h {
+                    for i in block_x..block_x + block_w {
+                        let pixel = premultiplied(self.get_pixel(i, j));
+
+                        for (s, channel) in sum.iter_mut().zip(&pixel) {
+                            *s += channel;
+                        }
+                    }
+                }
+
+                let count = (block_w * block_h) as f32;
+                let average = from_premultiplied(sum.map(|s| s / count));
+                self.draw_rect(block_x, block_y, block_w, block_h, average);
+            }
+        }
+    }
+
+    /// Blurs the rectangle with a Gaussian kernel reaching `radius` pixels away. Only pixels
+    /// inside the rectangle are taken into account, so that nothing around it leaks in. The
+    /// rectangle must be in bounds.
+    pub fn blur(&mut self, x: u32, y: u32, w: u32, h: u32, radius: u32) {
+        let radius = radius as i32;
+        let sigma = (radius as f32 / 3.0).max(0.5);
+        let mut kernel: Vec<f32> = (-radius..=radius)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let sum: f32 = kernel.iter().sum();
+        kernel.iter_mut().for_each(|k| *k /= sum);
+
+        let (w, h) = (w as usize, h as usize);
+        let mut pixels: Vec<[f32; 4]> = Vec::with_capacity(w * h);
+        for j in y..y + h as u32 {
+            for i in x..x + w as u32 {
+                pixels.push(premultiplied(self.get_pixel(i, j)));
+            }
+        }
+
+        // Blur the rows, then the columns
+        let pixels = convolve_lines(&pixels, &kernel, (h, w), (w, 1));
+        let pixels = convolve_lines(&pixels, &kernel, (w, 1), (h, w));
 
+        for (index, pixel) in pixels.into_iter().enumerate() {
+            let (i, j) = ((index % w) as u32, (index / w) as u32);
+            self.put_pixel(x + i, y + j, from_premultiplied(pixel));
+        }
+    }
 }
 
 // Signed draw functions (i32)
 impl CanvasBuffer {
-    pub fn draw_square_s(&mut self, x: i32, y: i32, size: i32, color: BufColor) {
-        let half = (size as f64 / 2.0) as i32;
-        self.draw_rect_s(x - half, y - half, size, size, color);
+    /// Blends the color over the pixel, scaling its alpha by `coverage` (between 0 and 1).
+    /// Pixels out of bounds are ignored.
+    pub fn blend_pixel_s(&mut self, x: i32, y: i32, color: BufColor, coverage: f32) {
+        if x < 0 || y < 0 || !self.in_bounds(x as u32, y as u32) {
+            return;
+        }
+
+        let alpha = (color.alpha() as f32 * coverage.clamp(0.0, 1.0)).round() as u8;
+        let pix = self.get_pixel_mut(x as u32, y as u32);
+        *pix = color.with_alpha(alpha).over(*pix);
     }
 
     pub fn draw_rect_s(&mut self, x: i32, y: i32, w: i32, h: i32, color: BufColor) {
@@ -150,11 +746,186 @@ impl CanvasBuffer {
             }
         }
     }
+
+    /// Composites `other` over this buffer at the given position. Pixels that fall out of
+    /// bounds are ignored.
+    pub fn blend_buffer_s(&mut self, x: i32, y: i32, other: &CanvasBuffer) {
+        let x_min = x.max(0);
+        let x_max = (x + other.width as i32).min(self.width as i32);
+        let y_min = y.max(0);
+        let y_max = (y + other.height as i32).min(self.height as i32);
+
+        for j in y_min..y_max {
+            for i in x_min..x_max {
+                let color = other.get_pixel((i - x) as u32, (j - y) as u32);
+                let pix = self.get_pixel_mut(i as u32, j as u32);
+                *pix = color.over(*pix);
+            }
+        }
+    }
+
+    /// Draws the outline of a rectangle, `thickness` pixels wide towards its inside.
+    pub fn draw_rect_outline_s(
+        &mut self,
+        x: i32,
+        y: i32,
+        w: i32,
+        h: i32,
+        thickness: i32,
+        color: BufColor,
+    ) {
+        if thickness * 2 >= w || thickness * 2 >= h {
+            self.draw_rect_s(x, y, w, h, color);
+            return;
+        }
+
+        let inner_h = h - 2 * thickness;
+        self.draw_rect_s(x, y, w, thickness, color);
+        self.draw_rect_s(x, y + h - thickness, w, thickness, color);
+        self.draw_rect_s(x, y + thickness, thickness, inner_h, color);
+        self.draw_rect_s(x + w - thickness, y + thickness, thickness, inner_h, color);
+    }
+}
+
+// Anti-aliased draw functions (f32). Coordinates refer to the top left corner of pixels,
+// so the center of pixel (0, 0) is at (0.5, 0.5).
+impl CanvasBuffer {
+    /// Draws a line with round caps.
+    pub fn draw_line_aa(&mut self, a: (f32, f32), b: (f32, f32), width: f32, color: BufColor) {
+        let radius = width / 2.0;
+        let (ab_x, ab_y) = (b.0 - a.0, b.1 - a.1);
+        let len_sq = ab_x * ab_x + ab_y * ab_y;
+
+        let x_min = (a.0.min(b.0) - radius - 1.0).floor() as i32;
+        let x_max = (a.0.max(b.0) + radius + 1.0).ceil() as i32;
+        let y_min = (a.1.min(b.1) - radius - 1.0).floor() as i32;
+        let y_max = (a.1.max(b.1) + radius + 1.0).ceil() as i32;
+
+        for y in y_min.max(0)..y_max.min(self.height as i32) {
+            for x in x_min.max(0)..x_max.min(self.width as i32) {
+                let (px, py) = (x as f32 + 0.5 - a.0, y as f32 + 0.5 - a.1);
+
+                // Distance from the pixel center to the closest point of the segment
+                let t = if len_sq > 0.0 {
+                    ((px * ab_x + py * ab_y) / len_sq).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let dist = ((px - t * ab_x).powi(2) + (py - t * ab_y).powi(2)).sqrt();
+
+                self.blend_pixel_s(x, y, color, radius - dist + 0.5);
+            }
+        }
+    }
+
+    pub fn fill_triangle_aa(&mut self, points: [(f32, f32); 3], color: BufColor) {
+        let [a, b, c] = points;
+        // Orient edges so that the inside is on the positive side
+        let orientation = ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)).signum();
+        let edges = [(a, b), (b, c), (c, a)];
+
+        let x_min = a.0.min(b.0).min(c.0).floor() as i32 - 1;
+        let x_max = a.0.max(b.0).max(c.0).ceil() as i32 + 1;
+        let y_min = a.1.min(b.1).min(c.1).floor() as i32 - 1;
+        let y_max = a.1.max(b.1).max(c.1).ceil() as i32 + 1;
+
+        for y in y_min.max(0)..y_max.min(self.height as i32) {
+            for x in x_min.max(0)..x_max.min(self.width as i32) {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+                // Signed distance to the closest edge, positive inside
+                let dist = edges
+                    .iter()
+                    .map(|(p, q)| {
+                        let (ex, ey) = (q.0 - p.0, q.1 - p.1);
+                        let len = (ex * ex + ey * ey).sqrt().max(f32::EPSILON);
+                        orientation * (ex * (py - p.1) - ey * (px - p.0)) / len
+                    })
+                    .fold(f32::INFINITY, f32::min);
+
+                self.blend_pixel_s(x, y, color, dist + 0.5);
+            }
+        }
+    }
+
+    /// Fills an axis-aligned ellipse. If `width` is given, only an outline of that width is
+    /// drawn instead, towards the inside of the ellipse.
+    pub fn draw_ellipse_aa(
+        &mut self,
+        center: (f32, f32),
+        radii: (f32, f32),
+        width: Option<f32>,
+        color: BufColor,
+    ) {
+        let (rx, ry) = radii;
+
+        if rx <= 0.0 || ry <= 0.0 {
+            return;
+        }
+
+        let x_min = (center.0 - rx).floor() as i32 - 1;
+        let x_max = (center.0 + rx).ceil() as i32 + 1;
+        let y_min = (center.1 - ry).floor() as i32 - 1;
+        let y_max = (center.1 + ry).ceil() as i32 + 1;
+
+        for y in y_min.max(0)..y_max.min(self.height as i32) {
+            for x in x_min.max(0)..x_max.min(self.width as i32) {
+                let (px, py) = (x as f32 + 0.5 - center.0, y as f32 + 0.5 - center.1);
+
+                // Approximate the signed distance to the ellipse (positive outside) with
+                // the implicit function divided by the length of its gradient.
+                let f = (px / rx).powi(2) + (py / ry).powi(2) - 1.0;
+                let grad = ((2.0 * px / (rx * rx)).powi(2) + (2.0 * py / (ry * ry)).powi(2)).sqrt();
+                let dist = f / grad.max(f32::EPSILON);
+
+                let coverage = match width {
+                    Some(width) => (0.5 - dist).min(dist + width + 0.5),
+                    None => 0.5 - dist,
+                };
+                self.blend_pixel_s(x, y, color, coverage);
+            }
+        }
+    }
+
+    /// Draws an arrow pointing from `tail` to `head`. The arrowhead is `head_len` long and
+    /// as wide as it is long.
+    pub fn draw_arrow_aa(
+        &mut self,
+        tail: (f32, f32),
+        head: (f32, f32),
+        width: f32,
+        head_len: f32,
+        color: BufColor,
+    ) {
+        let (dx, dy) = (head.0 - tail.0, head.1 - tail.1);
+        let len = (dx * dx + dy * dy).sqrt();
+
+        if len == 0.0 {
+            return;
+        }
+
+        let head_len = head_len.min(len);
+        let (ux, uy) = (dx / len, dy / len);
+        let base = (head.0 - ux * head_len, head.1 - uy * head_len);
+        let half = head_len / 2.0;
+
+        // End the shaft inside the arrowhead, so that its cap doesn't poke out of the tip
+        let shaft_end = (head.0 - ux * head_len / 2.0, head.1 - uy * head_len / 2.0);
+        self.draw_line_aa(tail, shaft_end, width, color);
+        self.fill_triangle_aa(
+            [
+                head,
+                (base.0 - uy * half, base.1 + ux * half),
+                (base.0 + uy * half, base.1 - ux * half),
+            ],
+            color,
+        );
+    }
 }
 
 // UI draw functions
 impl CanvasBuffer {
-    pub fn draw_colorbar(&mut self, colors: &[RgbColor], selected: u32, pos: ColorbarPos) {
+    pub fn draw_colorbar(&mut self, colors: &[RgbaColor], selected: u32, pos: ColorbarPos) {
         let count = colors.len() as u32;
         let box_size = 32.min((self.width / count).max(3));
         let padding = 2.min((self.width as i32 - (box_size * count) as i32).max(0) as u32 / count);
@@ -178,9 +949,9 @@ impl CanvasBuffer {
             // border color
             let bc = if i == selected {
                 // Invert the color
-                BufColor(0x00FFFFFF - color.0)
+                color.inverted()
             } else {
-                BufColor(0)
+                BufColor::BLACK
             };
 
             self.draw_rect(x, y, box_size, box_size, bc);
@@ -188,37 +959,393 @@ impl CanvasBuffer {
         }
     }
 
-    pub fn draw_guides(&mut self, a: Point, b: Point) {
-        let (bw, bh) = (self.width() as i32, self.height() as i32);
-        let mut invert_pixel = move |x, y| {
-            let pix = self.get_pixel_mut(x as u32, y as u32);
-            let inverted = BufColor(0x00FFFFFF - pix.0);
+    /// Draws text with the built-in bitmap font, scaling every pixel of the font into a
+    /// `scale` pixels wide square. Lines are separated by `'\n'`. The text is padded by one
+    /// pixel of the font, and `(x, y)` is the top left corner of the padding, which is
+    /// filled with `background` if given. See `label_size` for the size of the whole label.
+    pub fn draw_label(
+        &mut self,
+        x: u32,
+        y: u32,
+        text: &str,
+        foreground: BufColor,
+        background: Option<BufColor>,
+        scale: u32,
+    ) {
+        let mut blend_square = |x: u32, y: u32, size: (u32, u32), color: BufColor| {
+            for pix_y in y..y.saturating_add(size.1).min(self.height) {
+                for pix_x in x..x.saturating_add(size.0).min(self.width) {
+                    let pix = self.get_pixel_mut(pix_x, pix_y);
+                    *pix = color.over(*pix);
+                }
+            }
+        };
 
-            self.put_pixel(x as u32, y as u32, inverted);
+        if let Some(background) = background {
+            blend_square(x, y, label_size(text, scale), background);
+        }
+
+        for (line_i, line) in text.lines().enumerate() {
+            let line_y = y + scale * (1 + line_i as u32 * (GLYPH_HEIGHT + 1));
+
+            for (i, c) in line.chars().enumerate() {
+                let glyph_x = x + scale * (1 + i as u32 * (GLYPH_WIDTH + 1));
+
+                for (row_y, row) in glyph(c).iter().enumerate() {
+                    for col in 0..GLYPH_WIDTH {
+                        if row >> (GLYPH_WIDTH - 1 - col) & 1 == 1 {
+                            let pix_x = glyph_x + col * scale;
+                            let pix_y = line_y + row_y as u32 * scale;
+                            blend_square(pix_x, pix_y, (scale, scale), foreground);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws a bar along the bottom of the buffer with a line of text in it.
+    pub fn draw_status_bar(&mut self, text: &str) {
+        const PADDING: u32 = 2;
+        const BACKGROUND: BufColor = BufColor(0xD0000000);
+        const FOREGROUND: BufColor = BufColor(0xFFFFFFFF);
+
+        let height = label_size(text, 1).1 + 2 * PADDING;
+        let y = self.height.saturating_sub(height);
+
+        for pix_y in y..self.height {
+            for pix_x in 0..self.width {
+                let pix = self.get_pixel_mut(pix_x, pix_y);
+                *pix = BACKGROUND.over(*pix);
+            }
+        }
+
+        self.draw_label(PADDING, y + PADDING, text, FOREGROUND, None, 1);
+    }
+
+    /// Draws text asking the user for something, in a box at the center of the buffer.
+    pub fn draw_prompt(&mut self, text: &str) {
+        const SCALE: u32 = 2;
+        const BACKGROUND: BufColor = BufColor(0xE0202020);
+        const FOREGROUND: BufColor = BufColor(0xFFFFFFFF);
+
+        let (width, height) = label_size(text, SCALE);
+        let x = self.width.saturating_sub(width) / 2;
+        let y = self.height.saturating_sub(height) / 2;
+
+        self.draw_label(x, y, text, FOREGROUND, Some(BACKGROUND), SCALE);
+    }
+
+    /// Draws a message for the user in a box at the top of the buffer.
+    pub fn draw_notice(&mut self, text: &str) {
+        const MARGIN: u32 = 8;
+        const BACKGROUND: BufColor = BufColor(0xE0202020);
+        const FOREGROUND: BufColor = BufColor(0xFFFFFFFF);
+
+        let (width, _) = label_size(text, 1);
+        let x = self.width.saturating_sub(width) / 2;
+
+        self.draw_label(x, MARGIN, text, FOREGROUND, Some(BACKGROUND), 1);
+    }
+
+    /// Draws a magnified view of the pixels of `source` around `pixel`, next to `pos`.
+    pub fn draw_loupe(&mut self, source: &CanvasBuffer, pixel: Point, pos: Point) {
+        const RADIUS: i32 = 5;
+        const ZOOM: i32 = 8;
+        const OFFSET: i32 = 16;
+
+        let size = (2 * RADIUS + 1) * ZOOM;
+        let (bw, bh) = (self.width as i32, self.height as i32);
+
+        // Keep the loupe inside the buffer, flipping it to the other side of the cursor
+        let x = if pos.0 + OFFSET + size > bw {
+            pos.0 - OFFSET - size
+        } else {
+            pos.0 + OFFSET
+        };
+        let y = if pos.1 + OFFSET + size > bh {
+            pos.1 - OFFSET - size
+        } else {
+            pos.1 + OFFSET
         };
 
-        if a.1 > 0 && a.1 < bh {
-            for x in 0..bw {
-                invert_pixel(x, a.1);
+        self.draw_rect_outline_s(x - 1, y - 1, size + 2, size + 2, 1, BufColor::BLACK);
+
+        let source_pixel = |x: i32, y: i32| {
+            if x >= 0 && y >= 0 && source.in_bounds(x as u32, y as u32) {
+                source.get_pixel(x as u32, y as u32)
+            } else {
+                BufColor::TRANSPARENT
+            }
+        };
+
+        for j in -RADIUS..=RADIUS {
+            for i in -RADIUS..=RADIUS {
+                let color = source_pixel(pixel.0 + i, pixel.1 + j);
+                let (px, py) = (x + (i + RADIUS) * ZOOM, y + (j + RADIUS) * ZOOM);
+
+                self.draw_rect_s(px, py, ZOOM, ZOOM, color);
             }
         }
 
-        if b.1 > 0 && b.1 < bh && a.1 != b.1 {
-            for x in 0..bw {
-                invert_pixel(x, b.1);
+        // Outline the pixel under the cursor
+        let color = source_pixel(pixel.0, pixel.1).with_alpha(0xFF).inverted();
+        let (cx, cy) = (x + RADIUS * ZOOM, y + RADIUS * ZOOM);
+        self.draw_rect_outline_s(cx - 1, cy - 1, ZOOM + 2, ZOOM + 2, 1, color);
+    }
+
+    /// Inverts the pixels along the outline of the quadrilateral with the given corners.
+    pub fn draw_selection_outline(&mut self, corners: [Point; 4]) {
+        let mut points = Vec::new();
+
+        for (i, a) in corners.iter().enumerate() {
+            let b = corners[(i + 1) % corners.len()];
+            let steps = (b.0 - a.0).abs().max((b.1 - a.1).abs()).max(1);
+
+            for step in 0..=steps {
+                let t = step as f32 / steps as f32;
+                let x = a.0 + ((b.0 - a.0) as f32 * t).round() as i32;
+                let y = a.1 + ((b.1 - a.1) as f32 * t).round() as i32;
+                points.push((x, y));
             }
         }
 
-        if a.0 > 0 && a.0 < bw {
-            for y in 0..bh {
-                invert_pixel(a.0, y);
+        // Corners are shared by two sides, so avoid inverting them twice
+        points.sort_unstable();
+        points.dedup();
+
+        self.invert_points(&points);
+    }
+
+    /// Inverts the given pixels, making them opaque so that they show on any background.
+    pub fn invert_points(&mut self, points: &[Point]) {
+        let (bw, bh) = (self.width() as i32, self.height() as i32);
+
+        for &(x, y) in points {
+            if x >= 0 && y >= 0 && x < bw && y < bh {
+                let pix = self.get_pixel_mut(x as u32, y as u32);
+                *pix = pix.with_alpha(0xFF).inverted();
             }
         }
+    }
+
+    /// Draws a handle that can be dragged with the mouse, either square or round.
+    pub fn draw_handle(&mut self, center: (f32, f32), round: bool) {
+        const RADIUS: f32 = 3.5;
+        const WHITE: BufColor = BufColor(0xFFFFFFFF);
+
+        if round {
+            self.draw_ellipse_aa(center, (RADIUS, RADIUS), None, WHITE);
+            self.draw_ellipse_aa(center, (RADIUS, RADIUS), Some(1.0), BufColor::BLACK);
+        } else {
+            let size = (RADIUS * 2.0) as i32;
+            let x = (center.0 - RADIUS).round() as i32;
+            let y = (center.1 - RADIUS).round() as i32;
+            self.draw_rect_s(x, y, size, size, WHITE);
+            self.draw_rect_outline_s(x, y, size, size, 1, BufColor::BLACK);
+        }
+    }
+
+    /// Composites every pixel over a checkerboard, so that transparent regions can be told
+    /// apart when displayed.
+    pub fn flatten_onto_checkerboard(&mut self) {
+        const CHECKER_SIZE: u32 = 8;
+        const LIGHT: BufColor = BufColor(0xFFFFFFFF);
+        const DARK: BufColor = BufColor(0xFFCCCCCC);
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pix = self.get_pixel_mut(x, y);
 
-        if b.0 > 0 && b.0 < bw && a.0 != b.0 {
-            for y in 0..bh {
-                invert_pixel(b.0, y);
+                if pix.alpha() != 0xFF {
+                    let light = (x / CHECKER_SIZE + y / CHECKER_SIZE).is_multiple_of(2);
+                    *pix = pix.over(if light { LIGHT } else { DARK });
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: BufColor = BufColor(0xFFFFFFFF);
+    /// Differs from white by 15 in each color channel.
+    const GREY: BufColor = BufColor(0xFFF0F0F0);
+    const FILL: BufColor = BufColor(0xFFFF0000);
+
+    /// Splits a picture drawn with `.` (white), `g` (grey), `#` (black) and `o` (fill) into
+    /// its rows.
+    fn rows(picture: &str) -> Vec<String> {
+        picture
+            .lines()
+            .map(str::trim)
+            .filter(|row| !row.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Fills a picture from the given pixel and returns the result as a picture.
+    fn fill(picture: &str, (x, y): (u32, u32), tolerance: u8, diagonal: bool) -> Vec<String> {
+        let rows = rows(picture);
+        let data = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|c| match c {
+                '.' => WHITE,
+                'g' => GREY,
+                '#' => BufColor::BLACK,
+                'o' => FILL,
+                _ => panic!("unknown pixel `{}`", c),
+            })
+            .collect();
+
+        let mut buffer = CanvasBuffer::new(data, rows[0].len() as u32, rows.len() as u32);
+        buffer.flood_fill(x, y, FILL, tolerance, diagonal);
+        draw_rows(&buffer)
+    }
+
+    /// Turns the buffer back into the rows of a picture, the way `rows` reads them.
+    fn draw_rows(buffer: &CanvasBuffer) -> Vec<String> {
+        buffer
+            .data()
+            .chunks(buffer.width() as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|pix| match *pix {
+                        WHITE => '.',
+                        GREY => 'g',
+                        BufColor::BLACK => '#',
+                        FILL => 'o',
+                        _ => '?',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flood_fill_4_connected_stops_at_corners() {
+        let picture = "
+            ..#..
+            ..#..
+            ###..
+            ...#.
+            ...#.
+        ";
+        let filled = "
+            oo#..
+            oo#..
+            ###..
+            ...#.
+            ...#.
+        ";
+
+        assert_eq!(fill(picture, (0, 0), 0, false), rows(filled));
+    }
+
+    #[test]
+    fn flood_fill_8_connected_crosses_corners() {
+        // The top left pixel only touches the one at (1, 1) by a corner
+        let picture = "
+            .#...
+            #.#..
+            .#.#.
+            ...#.
+        ";
+        let filled_4 = "
+            o#...
+            #.#..
+            .#.#.
+            ...#.
+        ";
+        let filled_8 = "
+            o#ooo
+            #o#oo
+            o#o#o
+            ooo#o
+        ";
+
+        assert_eq!(fill(picture, (0, 0), 0, false), rows(filled_4));
+        assert_eq!(fill(picture, (0, 0), 0, true), rows(filled_8));
+    }
+
+    #[test]
+    fn flood_fill_tolerance() {
+        let picture = "
+            ..g#.
+            gg.#.
+        ";
+        let strict = "
+            oog#.
+            gg.#.
+        ";
+        let tolerant = "
+            ooo#.
+            ooo#.
+        ";
+
+        assert_eq!(fill(picture, (0, 0), 14, false), rows(strict));
+        assert_eq!(fill(picture, (0, 0), 15, false), rows(tolerant));
+    }
+
+    #[test]
+    fn flood_fill_from_edges() {
+        let picture = "
+            ....
+            .##.
+            .#..
+        ";
+        let filled = "
+            oooo
+            o##o
+            o#oo
+        ";
+
+        for seed in [(3, 2), (0, 2), (3, 0), (2, 0)] {
+            assert_eq!(fill(picture, seed, 0, false), rows(filled));
+            assert_eq!(fill(picture, seed, 0, true), rows(filled));
+        }
+        assert_eq!(fill(".", (0, 0), 0, true), rows("o"));
+    }
+
+    #[test]
+    fn flood_fill_with_the_same_color() {
+        // Filled pixels still match the starting color, which must not keep the fill going
+        let picture = "
+            oo#
+            o.#
+            ooo
+        ";
+        let everything = "
+            ooo
+            ooo
+            ooo
+        ";
+
+        assert_eq!(fill(picture, (0, 0), 0, false), rows(picture));
+        assert_eq!(fill(picture, (0, 0), 255, true), rows(everything));
+    }
+
+    #[test]
+    fn labels_are_clipped_at_the_edges() {
+        let mut buffer = CanvasBuffer::new(vec![WHITE; 8 * 5], 8, 5);
+
+        // The label of "!" is 7x10 pixels, so only its top left part fits
+        buffer.draw_label(2, 1, "!", FILL, Some(BufColor::BLACK), 1);
+        let expected = "
+            ........
+            ..######
+            ..###o##
+            ..###o##
+            ..###o##
+        ";
+        assert_eq!(draw_rows(&buffer), rows(expected));
+
+        // Labels entirely out of the buffer draw nothing
+        buffer.draw_label(8, 0, "!", FILL, Some(BufColor::BLACK), 2);
+        buffer.draw_label(0, 40, "!", FILL, Some(BufColor::BLACK), 2);
+        assert_eq!(draw_rows(&buffer), rows(expected));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::app::EditorOptions;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Returns the default location of the config file: `$XDG_CONFIG_HOME/canved/config.toml`,
+/// falling back to `$HOME/.config/canved/config.toml`.
+pub fn default_config_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("canved").join("config.toml"))
+}
+
+/// Loads the editor options from the given config file, or from the default location if
+/// none is given. A missing default config file is not an error: the defaults are used
+/// instead. Fields missing from the file also take their default values.
+pub fn load_options(path: Option<&Path>) -> Result<EditorOptions, Box<dyn Error>> {
+    let (path, required) = match path {
+        Some(path) => (path.to_path_buf(), true),
+        None => match default_config_path() {
+            Some(path) => (path, false),
+            None => return Ok(EditorOptions::default()),
+        },
+    };
+
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+            return Ok(EditorOptions::default())
+        }
+        Err(e) => {
+            return Err(format!("could not read config file {}: {}", path.display(), e).into())
+        }
+    };
+
+    parse_options(&source)
+        .map_err(|e| format!("invalid config file {}: {}", path.display(), e).into())
+}
+
+fn parse_options(source: &str) -> Result<EditorOptions, Box<dyn Error>> {
+    let options: EditorOptions = toml::from_str(source)?;
+
+    if options.colors.is_empty() {
+        return Err("`colors` must contain at least one color".into());
+    }
+    if !(0.0..=1.0).contains(&options.highlighter.opacity) {
+        return Err("`highlighter.opacity` must be between 0 and 1".into());
+    }
+    if matches!(options.window.scale, Some(scale) if !(scale.is_finite() && scale > 0.0)) {
+        return Err("`window.scale` must be greater than 0".into());
+    }
+    let (max_size, geometry) = (options.window.max_size, options.window.geometry);
+    if max_size.0 == 0 || max_size.1 == 0 || matches!(geometry, Some((w, h)) if w == 0 || h == 0) {
+        return Err("window sizes must be greater than 0".into());
+    }
+    if options.bucket.connectivity != 4 && options.bucket.connectivity != 8 {
+        return Err("`bucket.connectivity` must be 4 or 8".into());
+    }
+
+    Ok(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> String {
+        match parse_options(source) {
+            Ok(_) => panic!("accepted {:?}", source),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn empty_file_gives_the_defaults() {
+        let (options, defaults) = (parse_options("").unwrap(), EditorOptions::default());
+
+        assert_eq!(options.brush, defaults.brush);
+        assert_eq!(options.highlighter, defaults.highlighter);
+        assert_eq!(options.mode.name(), defaults.mode.name());
+        assert_eq!(options.status_bar, defaults.status_bar);
+        assert_eq!(options.colors, defaults.colors);
+        assert_eq!(options.history, defaults.history);
+        assert_eq!(options.text, defaults.text);
+        assert_eq!(options.bucket, defaults.bucket);
+        assert_eq!(options.selection, defaults.selection);
+        assert_eq!(options.resize, defaults.resize);
+        assert_eq!(options.redact, defaults.redact);
+        assert_eq!(options.window, defaults.window);
+    }
+
+    #[test]
+    fn sections_override_some_fields() {
+        let options = parse_options("[brush]\nsize = 7\n\n[window]\nscale = 2.5\n").unwrap();
+
+        assert_eq!(options.brush.size, 7);
+        assert_eq!(options.brush.tip, EditorOptions::default().brush.tip);
+        assert_eq!(options.window.scale, Some(2.5));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(error("[brush]\nsise = 7\n").contains("sise"));
+        assert!(error("colours = []\n").contains("colours"));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for scale in ["0.0", "-1.0", "nan", "inf", "-inf"] {
+            let source = format!("[window]\nscale = {}\n", scale);
+            assert!(error(&source).contains("window.scale"), "{}", scale);
+        }
+        for opacity in ["-0.5", "1.5", "nan"] {
+            let source = format!("[highlighter]\nopacity = {}\n", opacity);
+            assert!(error(&source).contains("opacity"), "{}", opacity);
+        }
+        for connectivity in ["0", "6"] {
+            let source = format!("[bucket]\nconnectivity = {}\n", connectivity);
+            assert!(error(&source).contains("connectivity"), "{}", connectivity);
+        }
+        assert!(error("colors = []\n").contains("colors"));
+        assert!(error("[window]\nmax_size = [0, 600]\n").contains("window sizes"));
+    }
+}
@@ -0,0 +1,368 @@
+use crate::canvas::{BufColor, CanvasBuffer};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::mem;
+
+/// Limits on the size of the undo history. When a limit is exceeded, the oldest versions are
+/// forgotten. The newest version can always be undone, even if it alone exceeds `max_bytes`.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryLimits {
+    pub max_versions: Option<usize>,
+    pub max_bytes: Option<usize>,
+}
+
+/// The difference between two consecutive versions.
+enum Change {
+    /// Only the pixels inside a rectangle changed. Both the old and new contents of the
+    /// rectangle are kept.
+    Pixels {
+        x: u32,
+        y: u32,
+        before: CanvasBuffer,
+        after: CanvasBuffer,
+        /// Whether the pixels were moved around, like in a flip, instead of painted over.
+        moved: bool,
+    },
+    /// The dimensions of the image changed (e.g. a crop), so both whole images are kept.
+    Geometry {
+        before: CanvasBuffer,
+        after: CanvasBuffer,
+    },
+}
+
+impl Change {
+    /// Memory used by the stored pixels.
+    fn bytes(&self) -> usize {
+        let (before, after) = match self {
+            Change::Pixels { before, after, .. } => (before, after),
+            Change::Geometry { before, after } => (before, after),
+        };
+
+        (before.data().len() + after.data().len()) * mem::size_of::<BufColor>()
+    }
+
+    /// Whether the change moved pixels away from where they were, instead of only painting
+    /// over them.
+    fn moves_pixels(&self) -> bool {
+        matches!(
+            self,
+            Change::Pixels { moved: true, .. } | Change::Geometry { .. }
+        )
+    }
+}
+
+/// Undo history that stores the changes between versions instead of whole copies of them.
+pub struct History {
+    /// The version at `index`.
+    current: CanvasBuffer,
+    changes: VecDeque<Change>,
+    /// Number of changes applied to the oldest version to get the current one.
+    index: usize,
+    /// Memory used by all the changes.
+    bytes: usize,
+    /// Number of changes that moved pixels among those applied to get the current version,
+    /// including forgotten ones.
+    moves: usize,
+    limits: HistoryLimits,
+}
+
+impl History {
+    pub fn new(initial_buffer: CanvasBuffer, limits: HistoryLimits) -> Self {
+        Self {
+            current: initial_buffer,
+            changes: VecDeque::new(),
+            index: 0,
+            bytes: 0,
+            moves: 0,
+            limits,
+        }
+    }
+
+    pub fn current(&self) -> &CanvasBuffer {
+        &self.current
+    }
+
+    /// Number of versions that can be undone.
+    pub fn undo_depth(&self) -> usize {
+        self.index
+    }
+
+    /// Number of versions that can be redone.
+    pub fn redo_depth(&self) -> usize {
+        self.changes.len() - self.index
+    }
+
+    /// Whether every pixel of the current version is where it was in the initial version,
+    /// so that the two can be compared pixel by pixel. Crops, resizes, flips and rotations
+    /// move pixels, until they are undone.
+    pub fn aligned_with_initial(&self) -> bool {
+        self.moves == 0
+    }
+
+    /// Saves the buffer as a new version, deleting the undone versions that still exist.
+    /// Nothing is saved if the buffer is equal to the current version.
+    pub fn save(&mut self, buffer: &CanvasBuffer) {
+        self.push(buffer, false);
+    }
+
+    /// Saves the buffer as a new version whose pixels were moved around, like in a flip, so
+    /// that they no longer line up with the ones of the previous version.
+    pub fn save_moved(&mut self, buffer: &CanvasBuffer) {
+        self.push(buffer, true);
+    }
+
+    fn push(&mut self, buffer: &CanvasBuffer, moved: bool) {
+        let same_size =
+            buffer.width() == self.current.width() && buffer.height() == self.current.height();
+
+        let change = if same_size {
+            match self.current.diff_bounds(buffer) {
+                Some((x, y, w, h)) => Change::Pixels {
+                    x,
+                    y,
+                    before: self.current.copy_rect(x, y, w, h),
+                    after: buffer.copy_rect(x, y, w, h),
+                    moved,
+                },
+                None => return,
+            }
+        } else {
+            Change::Geometry {
+                before: self.current.clone(),
+                after: buffer.clone(),
+            }
+        };
+
+        for undone in self.changes.drain(self.index..) {
+            self.bytes -= undone.bytes();
+        }
+        self.bytes += change.bytes();
+        self.changes.push_back(change);
+        self.redo();
+        self.evict();
+    }
+
+    /// Forgets the oldest versions until the history is within its limits.
+    fn evict(&mut self) {
+        let max_versions = self.limits.max_versions.unwrap_or(usize::MAX).max(1);
+        let max_bytes = self.limits.max_bytes.unwrap_or(usize::MAX);
+
+        while self.changes.len() > max_versions
+            || (self.bytes > max_bytes && self.changes.len() > 1)
+        {
+            if let Some(oldest) = self.changes.pop_front() {
+                self.bytes -= oldest.bytes();
+                self.index -= 1;
+            }
+        }
+    }
+
+    /// Goes back one version. Returns false if there is nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        if self.index == 0 {
+            return false;
+        }
+
+        self.index -= 1;
+        let change = &self.changes[self.index];
+        match change {
+            Change::Pixels { x, y, before, .. } => self.current.paste(*x, *y, before),
+            Change::Geometry { before, .. } => self.current = before.clone(),
+        }
+        if change.moves_pixels() {
+            self.moves -= 1;
+        }
+        true
+    }
+
+    /// Goes forward one version. Returns false if there is nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        if self.index == self.changes.len() {
+            return false;
+        }
+
+        let change = &self.changes[self.index];
+        match change {
+            Change::Pixels { x, y, after, .. } => self.current.paste(*x, *y, after),
+            Change::Geometry { after, .. } => self.current = after.clone(),
+        }
+        if change.moves_pixels() {
+            self.moves += 1;
+        }
+        self.index += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: BufColor = BufColor(0xFFFF0000);
+    const BLUE: BufColor = BufColor(0xFF0000FF);
+
+    fn filled(width: u32, height: u32, color: BufColor) -> CanvasBuffer {
+        CanvasBuffer::new(vec![color; (width * height) as usize], width, height)
+    }
+
+    /// Saves a copy of the current version with one pixel changed.
+    fn save_pixel(history: &mut History, x: u32, y: u32, color: BufColor) -> CanvasBuffer {
+        let mut buffer = history.current().clone();
+        buffer.put_pixel(x, y, color);
+        history.save(&buffer);
+        buffer
+    }
+
+    fn same(a: &CanvasBuffer, b: &CanvasBuffer) -> bool {
+        a.width() == b.width() && a.height() == b.height() && a.data() == b.data()
+    }
+
+    #[test]
+    fn pixels_round_trip() {
+        let initial = filled(4, 3, BufColor::TRANSPARENT);
+        let mut history = History::new(initial.clone(), HistoryLimits::default());
+
+        let first = save_pixel(&mut history, 1, 1, RED);
+        let second = save_pixel(&mut history, 3, 2, BLUE);
+        assert_eq!((history.undo_depth(), history.redo_depth()), (2, 0));
+
+        // Only the changed pixel is stored
+        assert_eq!(history.bytes, 2 * 2 * mem::size_of::<BufColor>());
+
+        assert!(history.undo());
+        assert!(same(history.current(), &first));
+        assert!(history.undo());
+        assert!(same(history.current(), &initial));
+        assert!(!history.undo());
+        assert_eq!((history.undo_depth(), history.redo_depth()), (0, 2));
+
+        assert!(history.redo());
+        assert!(same(history.current(), &first));
+        assert!(history.redo());
+        assert!(same(history.current(), &second));
+        assert!(!history.redo());
+    }
+
+    #[test]
+    fn geometry_round_trip() {
+        let mut initial = filled(4, 3, RED);
+        initial.put_pixel(0, 0, BLUE);
+        let mut history = History::new(initial.clone(), HistoryLimits::default());
+
+        let mut rotated = initial.clone();
+        rotated.rotate_90();
+        history.save(&rotated);
+        let edited = save_pixel(&mut history, 0, 3, BufColor::TRANSPARENT);
+
+        assert!(history.undo());
+        assert!(same(history.current(), &rotated));
+        assert!(history.undo());
+        assert!(same(history.current(), &initial));
+
+        assert!(history.redo());
+        assert!(history.redo());
+        assert!(same(history.current(), &edited));
+    }
+
+    #[test]
+    fn unchanged_buffer_is_not_saved() {
+        let initial = filled(2, 2, RED);
+        let mut history = History::new(initial.clone(), HistoryLimits::default());
+
+        history.save(&initial);
+        assert_eq!(history.undo_depth(), 0);
+    }
+
+    #[test]
+    fn save_clears_redo() {
+        let mut history = History::new(filled(3, 3, RED), HistoryLimits::default());
+
+        save_pixel(&mut history, 0, 0, BLUE);
+        save_pixel(&mut history, 1, 0, BLUE);
+        history.undo();
+        history.undo();
+        assert_eq!(history.redo_depth(), 2);
+
+        let bytes = history.bytes;
+        let latest = save_pixel(&mut history, 2, 2, BLUE);
+        assert_eq!((history.undo_depth(), history.redo_depth()), (1, 0));
+        assert!(history.bytes < bytes);
+        assert!(!history.redo());
+        assert!(same(history.current(), &latest));
+    }
+
+    #[test]
+    fn moved_pixels_break_alignment_until_undone() {
+        let mut initial = filled(3, 2, RED);
+        initial.put_pixel(0, 0, BLUE);
+        let mut history = History::new(initial.clone(), HistoryLimits::default());
+
+        save_pixel(&mut history, 1, 1, BLUE);
+        assert!(history.aligned_with_initial());
+
+        let mut flipped = history.current().clone();
+        flipped.flip_horizontal();
+        history.save_moved(&flipped);
+        assert!(!history.aligned_with_initial());
+
+        let mut cropped = flipped.clone();
+        assert!(cropped.crop(0, 0, 2, 1));
+        history.save(&cropped);
+        history.undo();
+        assert!(!history.aligned_with_initial());
+        history.undo();
+        assert!(history.aligned_with_initial());
+        history.redo();
+        assert!(!history.aligned_with_initial());
+    }
+
+    #[test]
+    fn evicts_beyond_max_versions() {
+        let limits = HistoryLimits {
+            max_versions: Some(2),
+            max_bytes: None,
+        };
+        let mut history = History::new(filled(3, 3, RED), limits);
+
+        let first = save_pixel(&mut history, 0, 0, BLUE);
+        save_pixel(&mut history, 1, 0, BLUE);
+        let third = save_pixel(&mut history, 2, 0, BLUE);
+        assert_eq!(history.undo_depth(), 2);
+
+        // The oldest version is gone, so undoing stops at the first edit
+        assert!(history.undo());
+        assert!(history.undo());
+        assert!(!history.undo());
+        assert!(same(history.current(), &first));
+
+        assert!(history.redo());
+        assert!(history.redo());
+        assert!(same(history.current(), &third));
+    }
+
+    #[test]
+    fn evicts_beyond_max_bytes() {
+        // Each single pixel change stores two pixels
+        let change_bytes = 2 * mem::size_of::<BufColor>();
+        let limits = HistoryLimits {
+            max_versions: None,
+            max_bytes: Some(2 * change_bytes + 1),
+        };
+        let mut history = History::new(filled(3, 3, RED), limits);
+
+        for x in 0..3 {
+            save_pixel(&mut history, x, 1, BLUE);
+        }
+        assert_eq!(history.undo_depth(), 2);
+        assert_eq!(history.bytes, 2 * change_bytes);
+
+        // The newest version is kept even if it alone exceeds the limit
+        let mut big = history.current().clone();
+        big.crop(0, 0, 2, 2);
+        history.save(&big);
+        assert_eq!(history.undo_depth(), 1);
+        assert!(history.undo());
+        assert!(!history.undo());
+    }
+}
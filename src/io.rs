@@ -1,4 +1,4 @@
-use crate::canvas::{BufColor, CanvasBuffer, RgbColor};
+use crate::canvas::{BufColor, CanvasBuffer, RgbaColor};
 use image::io::Reader as ImageReader;
 use std::io::{self as io, Cursor, Read};
 use std::path::PathBuf;
@@ -25,7 +25,7 @@ impl ImageIo {
             ImageIo::File(path) => ImageReader::open(path)?.with_guessed_format()?.decode()?,
         };
 
-        Ok(image_to_buffer(img.into_rgb8()))
+        Ok(image_to_buffer(img.into_rgba8()))
     }
 
     pub fn write(
@@ -33,11 +33,34 @@ impl ImageIo {
         buffer: &CanvasBuffer,
         override_format: Option<ImageFormat>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let output_img = image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(
-            buffer.width(),
-            buffer.height(),
-            |x, y| image::Rgb(RgbColor::from(buffer.get_pixel(x, y)).0),
-        ));
+        let format: Option<image::ImageFormat> = match self {
+            ImageIo::Stdio => Some(override_format.unwrap_or(ImageFormat::Png).into()),
+            ImageIo::File(path) => override_format
+                .map(Into::into)
+                .or_else(|| image::ImageFormat::from_path(path).ok()),
+        };
+        // Opaque images are written without an alpha channel, as are formats that can't store it.
+        // Those are flattened onto white, so that transparent pixels don't show their color.
+        let has_alpha = buffer.data().iter().any(|c| c.alpha() != 0xFF);
+        let supports_alpha = format != Some(image::ImageFormat::Jpeg);
+
+        let output_img = if has_alpha && supports_alpha {
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(
+                buffer.width(),
+                buffer.height(),
+                |x, y| image::Rgba(RgbaColor::from(buffer.get_pixel(x, y)).0),
+            ))
+        } else {
+            image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(
+                buffer.width(),
+                buffer.height(),
+                |x, y| {
+                    let pix = buffer.get_pixel(x, y).over(BufColor(0xFFFFFFFF));
+                    let [r, g, b, _] = RgbaColor::from(pix).0;
+                    image::Rgb([r, g, b])
+                },
+            ))
+        };
 
         Ok(match self {
             ImageIo::Stdio => {
@@ -125,10 +148,10 @@ impl Into<image::ImageFormat> for ImageFormat {
     }
 }
 
-fn image_to_buffer(image: image::RgbImage) -> CanvasBuffer {
+fn image_to_buffer(image: image::RgbaImage) -> CanvasBuffer {
     let data: Vec<BufColor> = image
-        .chunks(3)
-        .map(|v| BufColor(((v[0] as u32) << 16) | ((v[1] as u32) << 8) | v[2] as u32))
+        .chunks(4)
+        .map(|v| BufColor::from(RgbaColor([v[0], v[1], v[2], v[3]])))
         .collect();
 
     CanvasBuffer::new(data, image.width(), image.height())
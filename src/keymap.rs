@@ -0,0 +1,565 @@
+use crate::mode::MODE_NAMES;
+use minifb::{Key, KeyRepeat, Window};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// Something the user can do by pressing a key chord.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Quit,
+    Undo,
+    Redo,
+    /// Cancel the operation in progress, or switch to view mode if there is none.
+    Cancel,
+    Copy,
+    Cut,
+    Paste,
+    /// Mirror the selection, or the whole image if nothing is selected, along its vertical
+    /// axis.
+    FlipHorizontal,
+    /// Mirror the selection, or the whole image if nothing is selected, along its
+    /// horizontal axis.
+    FlipVertical,
+    /// Rotate the selection, or the whole image if nothing is selected, a quarter turn
+    /// clockwise.
+    RotateCw,
+    /// Rotate the selection, or the whole image if nothing is selected, a quarter turn
+    /// counterclockwise.
+    RotateCcw,
+    /// Rotate the selection, or the whole image if nothing is selected, half a turn.
+    Rotate180,
+    /// Ask for a new size for the image.
+    Resize,
+    /// Switch to the mode with the given name (one of `MODE_NAMES`).
+    Enter(&'static str),
+    /// Select the color at the given palette index.
+    SelectColor(u32),
+    /// Zoom in to the next zoom level.
+    ZoomIn,
+    /// Zoom out to the previous zoom level.
+    ZoomOut,
+    /// Zoom to fit the whole image in the window.
+    ZoomFit,
+    /// Zoom to the given percentage, 100 showing each pixel of the image as one pixel of the
+    /// screen.
+    Zoom(u32),
+    /// Show or hide the status bar.
+    ToggleStatusBar,
+    /// Switch to the other variant of the current mode: between outlined and filled shapes,
+    /// between painting and inverting with the brush, or to the next redaction style. Chords
+    /// are bound regardless of the mode, so one action covers every mode with variants.
+    ToggleVariant,
+}
+
+impl Action {
+    /// Whether the action is triggered again while the chord is held down.
+    pub fn repeats(self) -> bool {
+        matches!(
+            self,
+            Action::Undo | Action::Redo | Action::ZoomIn | Action::ZoomOut
+        )
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || format!("unknown action `{}`", s);
+
+        match s {
+            "quit" => Ok(Action::Quit),
+            "undo" => Ok(Action::Undo),
+            "redo" => Ok(Action::Redo),
+            "cancel" => Ok(Action::Cancel),
+            "copy" => Ok(Action::Copy),
+            "cut" => Ok(Action::Cut),
+            "paste" => Ok(Action::Paste),
+            "flip-horizontal" => Ok(Action::FlipHorizontal),
+            "flip-vertical" => Ok(Action::FlipVertical),
+            "rotate-cw" => Ok(Action::RotateCw),
+            "rotate-ccw" => Ok(Action::RotateCcw),
+            "rotate-180" => Ok(Action::Rotate180),
+            "resize" => Ok(Action::Resize),
+            "toggle-variant" => Ok(Action::ToggleVariant),
+            "toggle-status-bar" => Ok(Action::ToggleStatusBar),
+            "zoom-in" => Ok(Action::ZoomIn),
+            "zoom-out" => Ok(Action::ZoomOut),
+            "zoom-fit" => Ok(Action::ZoomFit),
+            _ => {
+                if let Some(name) = s.strip_prefix("enter-") {
+                    MODE_NAMES
+                        .iter()
+                        .find(|mode| **mode == name)
+                        .map(|mode| Action::Enter(mode))
+                        .ok_or_else(unknown)
+                } else if let Some(percent) = s.strip_prefix("zoom-") {
+                    percent
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|percent| *percent > 0)
+                        .map(Action::Zoom)
+                        .ok_or_else(unknown)
+                } else if let Some(n) = s.strip_prefix("select-color-") {
+                    n.parse::<u32>()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .map(|n| Action::SelectColor(n - 1))
+                        .ok_or_else(unknown)
+                } else {
+                    Err(unknown())
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Quit => write!(f, "quit"),
+            Action::Undo => write!(f, "undo"),
+            Action::Redo => write!(f, "redo"),
+            Action::Cancel => write!(f, "cancel"),
+            Action::Copy => write!(f, "copy"),
+            Action::Cut => write!(f, "cut"),
+            Action::Paste => write!(f, "paste"),
+            Action::FlipHorizontal => write!(f, "flip-horizontal"),
+            Action::FlipVertical => write!(f, "flip-vertical"),
+            Action::RotateCw => write!(f, "rotate-cw"),
+            Action::RotateCcw => write!(f, "rotate-ccw"),
+            Action::Rotate180 => write!(f, "rotate-180"),
+            Action::Resize => write!(f, "resize"),
+            Action::Enter(mode) => write!(f, "enter-{}", mode),
+            Action::SelectColor(i) => write!(f, "select-color-{}", i + 1),
+            Action::ZoomIn => write!(f, "zoom-in"),
+            Action::ZoomOut => write!(f, "zoom-out"),
+            Action::ZoomFit => write!(f, "zoom-fit"),
+            Action::Zoom(percent) => write!(f, "zoom-{}", percent),
+            Action::ToggleVariant => write!(f, "toggle-variant"),
+            Action::ToggleStatusBar => write!(f, "toggle-status-bar"),
+        }
+    }
+}
+
+/// Names used for keys in the config file.
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("0", Key::Key0),
+    ("1", Key::Key1),
+    ("2", Key::Key2),
+    ("3", Key::Key3),
+    ("4", Key::Key4),
+    ("5", Key::Key5),
+    ("6", Key::Key6),
+    ("7", Key::Key7),
+    ("8", Key::Key8),
+    ("9", Key::Key9),
+    ("a", Key::A),
+    ("b", Key::B),
+    ("c", Key::C),
+    ("d", Key::D),
+    ("e", Key::E),
+    ("f", Key::F),
+    ("g", Key::G),
+    ("h", Key::H),
+    ("i", Key::I),
+    ("j", Key::J),
+    ("k", Key::K),
+    ("l", Key::L),
+    ("m", Key::M),
+    ("n", Key::N),
+    ("o", Key::O),
+    ("p", Key::P),
+    ("q", Key::Q),
+    ("r", Key::R),
+    ("s", Key::S),
+    ("t", Key::T),
+    ("u", Key::U),
+    ("v", Key::V),
+    ("w", Key::W),
+    ("x", Key::X),
+    ("y", Key::Y),
+    ("z", Key::Z),
+    ("f1", Key::F1),
+    ("f2", Key::F2),
+    ("f3", Key::F3),
+    ("f4", Key::F4),
+    ("f5", Key::F5),
+    ("f6", Key::F6),
+    ("f7", Key::F7),
+    ("f8", Key::F8),
+    ("f9", Key::F9),
+    ("f10", Key::F10),
+    ("f11", Key::F11),
+    ("f12", Key::F12),
+    ("down", Key::Down),
+    ("left", Key::Left),
+    ("right", Key::Right),
+    ("up", Key::Up),
+    ("apostrophe", Key::Apostrophe),
+    ("backquote", Key::Backquote),
+    ("backslash", Key::Backslash),
+    ("comma", Key::Comma),
+    ("equal", Key::Equal),
+    ("leftbracket", Key::LeftBracket),
+    ("minus", Key::Minus),
+    ("period", Key::Period),
+    ("rightbracket", Key::RightBracket),
+    ("semicolon", Key::Semicolon),
+    ("slash", Key::Slash),
+    ("backspace", Key::Backspace),
+    ("delete", Key::Delete),
+    ("end", Key::End),
+    ("enter", Key::Enter),
+    ("escape", Key::Escape),
+    ("home", Key::Home),
+    ("insert", Key::Insert),
+    ("pagedown", Key::PageDown),
+    ("pageup", Key::PageUp),
+    ("space", Key::Space),
+    ("tab", Key::Tab),
+];
+
+/// A key together with the modifiers that must be held while pressing it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct KeyChord {
+    pub key: Key,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl KeyChord {
+    fn new(key: Key) -> Self {
+        Self {
+            key,
+            ctrl: false,
+            shift: false,
+            alt: false,
+        }
+    }
+
+    fn ctrl(mut self) -> Self {
+        self.ctrl = true;
+        self
+    }
+
+    fn shift(mut self) -> Self {
+        self.shift = true;
+        self
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_lowercase();
+        let mut parts: Vec<&str> = lower.split('+').map(str::trim).collect();
+        let key_name = parts.pop().unwrap_or_default();
+
+        let key = KEY_NAMES
+            .iter()
+            .find(|(name, _)| *name == key_name)
+            .map(|(_, key)| *key)
+            .ok_or_else(|| format!("unknown key `{}` in `{}`", key_name, s))?;
+        let mut chord = KeyChord::new(key);
+
+        for modifier in parts {
+            match modifier {
+                "ctrl" | "control" => chord.ctrl = true,
+                "shift" => chord.shift = true,
+                "alt" => chord.alt = true,
+                _ => return Err(format!("unknown modifier `{}` in `{}`", modifier, s)),
+            }
+        }
+
+        Ok(chord)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "ctrl+")?;
+        }
+        if self.shift {
+            write!(f, "shift+")?;
+        }
+        if self.alt {
+            write!(f, "alt+")?;
+        }
+
+        let name = KEY_NAMES
+            .iter()
+            .find(|(_, key)| *key == self.key)
+            .map_or("?", |(name, _)| name);
+        write!(f, "{}", name)
+    }
+}
+
+/// One or more chords bound to an action in the config file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ChordList {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Maps key chords to actions. In the config file, the `[keys]` table binds action names to
+/// one chord or a list of chords, replacing the default chords of those actions.
+#[derive(Clone, Deserialize, Debug)]
+#[serde(try_from = "BTreeMap<String, ChordList>")]
+pub struct Keymap {
+    bindings: HashMap<KeyChord, Action>,
+}
+
+impl Keymap {
+    /// Builds the keymap, failing if a chord is bound to more than one action.
+    fn from_bindings(bindings: Vec<(Action, Vec<KeyChord>)>) -> Result<Self, String> {
+        let mut map = HashMap::new();
+
+        for (action, chords) in bindings {
+            for chord in chords {
+                match map.insert(chord, action) {
+                    Some(other) if other != action => {
+                        return Err(format!(
+                            "`{}` is bound to both `{}` and `{}`",
+                            chord, other, action
+                        ));
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        Ok(Self { bindings: map })
+    }
+
+    /// Returns the actions whose chords were pressed since the last window update.
+    pub fn pressed_actions(&self, window: &Window) -> Vec<Action> {
+        let ctrl = window.is_key_down(Key::LeftCtrl) || window.is_key_down(Key::RightCtrl);
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        let alt = window.is_key_down(Key::LeftAlt) || window.is_key_down(Key::RightAlt);
+
+        window
+            .get_keys_pressed(KeyRepeat::Yes)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|key| {
+                let chord = KeyChord {
+                    key,
+                    ctrl,
+                    shift,
+                    alt,
+                };
+
+                self.bindings
+                    .get(&chord)
+                    .copied()
+                    .filter(|action| action.repeats() || window.is_key_pressed(key, KeyRepeat::No))
+            })
+            .collect()
+    }
+}
+
+fn default_bindings() -> Vec<(Action, Vec<KeyChord>)> {
+    let color_keys = [
+        Key::Key1,
+        Key::Key2,
+        Key::Key3,
+        Key::Key4,
+        Key::Key5,
+        Key::Key6,
+        Key::Key7,
+        Key::Key8,
+        Key::Key9,
+    ];
+
+    let mut bindings = vec![
+        (Action::Quit, vec![KeyChord::new(Key::Q)]),
+        (Action::Undo, vec![KeyChord::new(Key::Z).ctrl()]),
+        (Action::Redo, vec![KeyChord::new(Key::Z).ctrl().shift()]),
+        (Action::Cancel, vec![KeyChord::new(Key::Escape)]),
+        (Action::Copy, vec![KeyChord::new(Key::C).ctrl()]),
+        (Action::Cut, vec![KeyChord::new(Key::X).ctrl()]),
+        (Action::Paste, vec![KeyChord::new(Key::V).ctrl()]),
+        (Action::FlipHorizontal, vec![KeyChord::new(Key::H)]),
+        (Action::FlipVertical, vec![KeyChord::new(Key::V)]),
+        (Action::RotateCw, vec![KeyChord::new(Key::RightBracket)]),
+        (Action::RotateCcw, vec![KeyChord::new(Key::LeftBracket)]),
+        (Action::Rotate180, vec![]),
+        (Action::Resize, vec![KeyChord::new(Key::R).ctrl()]),
+        (Action::ZoomIn, vec![KeyChord::new(Key::Equal).ctrl()]),
+        (Action::ZoomOut, vec![KeyChord::new(Key::Minus).ctrl()]),
+        (Action::ZoomFit, vec![KeyChord::new(Key::Key0).ctrl()]),
+        (Action::Zoom(100), vec![KeyChord::new(Key::Key1).ctrl()]),
+        (Action::Zoom(200), vec![KeyChord::new(Key::Key2).ctrl()]),
+        (Action::Enter("brush"), vec![KeyChord::new(Key::B)]),
+        (Action::Enter("highlighter"), vec![KeyChord::new(Key::M)]),
+        (Action::Enter("eraser"), vec![KeyChord::new(Key::E)]),
+        (Action::Enter("text"), vec![KeyChord::new(Key::T)]),
+        (Action::Enter("arrow"), vec![KeyChord::new(Key::A)]),
+        (Action::Enter("rectangle"), vec![KeyChord::new(Key::R)]),
+        (Action::Enter("ellipse"), vec![KeyChord::new(Key::O)]),
+        (Action::Enter("bucket"), vec![KeyChord::new(Key::F)]),
+        (Action::Enter("picker"), vec![KeyChord::new(Key::I)]),
+        (Action::Enter("redact"), vec![KeyChord::new(Key::X)]),
+        (Action::Enter("select"), vec![KeyChord::new(Key::S)]),
+        (Action::ToggleVariant, vec![KeyChord::new(Key::Tab)]),
+        (Action::ToggleStatusBar, vec![KeyChord::new(Key::F2)]),
+        (Action::Enter("crop"), vec![KeyChord::new(Key::C)]),
+    ];
+
+    for (i, key) in color_keys.iter().enumerate() {
+        bindings.push((Action::SelectColor(i as u32), vec![KeyChord::new(*key)]));
+    }
+
+    bindings
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_bindings(default_bindings()).expect("default key bindings conflict")
+    }
+}
+
+impl TryFrom<BTreeMap<String, ChordList>> for Keymap {
+    type Error = String;
+
+    fn try_from(overrides: BTreeMap<String, ChordList>) -> Result<Self, Self::Error> {
+        let mut bindings = default_bindings();
+
+        for (name, chords) in overrides {
+            let action: Action = name.parse()?;
+            let chords = match chords {
+                ChordList::One(chord) => vec![chord],
+                ChordList::Many(chords) => chords,
+            };
+            let chords = chords
+                .iter()
+                .map(|chord| chord.parse())
+                .collect::<Result<Vec<KeyChord>, _>>()?;
+
+            if let Some((_, old)) = bindings.iter_mut().find(|(a, _)| *a == action) {
+                *old = chords;
+            } else {
+                bindings.push((action, chords));
+            }
+        }
+
+        Self::from_bindings(bindings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_bindings_do_not_conflict() {
+        let keymap = Keymap::default();
+
+        assert_eq!(
+            keymap.bindings.get(&KeyChord::new(Key::Z).ctrl()),
+            Some(&Action::Undo)
+        );
+        assert_eq!(
+            keymap.bindings.get(&KeyChord::new(Key::Z).ctrl().shift()),
+            Some(&Action::Redo)
+        );
+    }
+
+    #[test]
+    fn chord_parses_modifiers() {
+        let chord: KeyChord = "Ctrl + Shift + Z".parse().unwrap();
+        assert_eq!(chord, KeyChord::new(Key::Z).ctrl().shift());
+
+        let chord: KeyChord = "control+alt+f2".parse().unwrap();
+        assert_eq!(
+            chord,
+            KeyChord {
+                key: Key::F2,
+                ctrl: true,
+                shift: false,
+                alt: true,
+            }
+        );
+    }
+
+    #[test]
+    fn chord_display_round_trips() {
+        for s in [
+            "q",
+            "ctrl+z",
+            "ctrl+shift+z",
+            "shift+alt+tab",
+            "ctrl+shift+alt+f12",
+        ] {
+            let chord: KeyChord = s.parse().unwrap();
+            assert_eq!(chord.to_string(), s);
+            assert_eq!(chord.to_string().parse::<KeyChord>(), Ok(chord));
+        }
+    }
+
+    #[test]
+    fn chord_rejects_unknown_names() {
+        assert!("ctrl+nope".parse::<KeyChord>().is_err());
+        assert!("super+z".parse::<KeyChord>().is_err());
+        assert!("".parse::<KeyChord>().is_err());
+        assert!("ctrl+".parse::<KeyChord>().is_err());
+    }
+
+    #[test]
+    fn action_display_round_trips() {
+        let actions = [
+            Action::Undo,
+            Action::Enter("select"),
+            Action::SelectColor(0),
+            Action::Zoom(250),
+            Action::ToggleStatusBar,
+            Action::ToggleVariant,
+        ];
+
+        for action in actions {
+            assert_eq!(action.to_string().parse::<Action>(), Ok(action));
+        }
+        assert!("enter-nowhere".parse::<Action>().is_err());
+        assert!("select-color-0".parse::<Action>().is_err());
+        assert!("zoom-0".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn conflicting_bindings_are_rejected() {
+        let z = KeyChord::new(Key::Z);
+
+        assert!(Keymap::from_bindings(vec![(Action::Undo, vec![z, z])]).is_ok());
+        assert!(
+            Keymap::from_bindings(vec![(Action::Undo, vec![z]), (Action::Redo, vec![z])]).is_err()
+        );
+    }
+
+    #[test]
+    fn overrides_replace_default_chords() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert(
+            String::from("undo"),
+            ChordList::Many(vec![String::from("u"), String::from("ctrl+u")]),
+        );
+        let keymap = Keymap::try_from(overrides).unwrap();
+
+        assert_eq!(
+            keymap.bindings.get(&KeyChord::new(Key::U)),
+            Some(&Action::Undo)
+        );
+        assert_eq!(keymap.bindings.get(&KeyChord::new(Key::Z).ctrl()), None);
+
+        // Binding a chord that is already taken by default is a conflict
+        let mut overrides = BTreeMap::new();
+        overrides.insert(String::from("undo"), ChordList::One(String::from("q")));
+        assert!(Keymap::try_from(overrides).is_err());
+    }
+}
@@ -1,13 +1,25 @@
 #[warn(clippy::pedantic)]
 mod app;
 mod canvas;
+mod config;
+mod font;
+mod history;
 mod io;
+mod keymap;
 mod mode;
+mod overlay;
+mod resize;
+mod stroke;
+mod text;
 mod util;
+mod viewport;
 
-use app::{edit, EditorOptions};
+use app::edit;
 use clap::Clap;
 use io::{ImageFormat, ImageIo};
+use resize::{parse_scale, ResizeTarget};
+use std::path::PathBuf;
+use util::{parse_geometry, parse_window_scale};
 
 #[derive(Clap)]
 #[clap(version = "0.1", author = "Aldo Acevedo <aldo@aael.xyz>")]
@@ -23,13 +35,49 @@ struct Opts {
     /// to a file, it is guessed by the extension.
     #[clap(short = 'F', long = "format")]
     output_format: Option<ImageFormat>,
+
+    /// Set config file. Defaults to $XDG_CONFIG_HOME/canved/config.toml
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+
+    /// Resize the image before editing. WxH fits it in that size keeping its aspect ratio,
+    /// W or xH only set one side and WxH! stretches it to exactly that size
+    #[clap(long, value_name = "SIZE")]
+    resize: Option<ResizeTarget>,
+
+    /// Scale the image by a percentage before editing, e.g. 50%
+    #[clap(long, value_name = "PERCENT", parse(try_from_str = parse_scale), conflicts_with = "resize")]
+    scale: Option<ResizeTarget>,
+
+    /// Set the size of the window, e.g. 1280x720. By default, the window fits the image
+    /// within the maximum size set in the config file
+    #[clap(long, value_name = "WxH", parse(try_from_str = parse_geometry))]
+    geometry: Option<(u32, u32)>,
+
+    /// Set the size of the window to that of the image times a factor, e.g. 2 or 0.5. Only
+    /// changes how the image is shown: --scale resizes the image itself
+    #[clap(long, value_name = "FACTOR", parse(try_from_str = parse_window_scale), conflicts_with = "geometry")]
+    window_scale: Option<f32>,
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opts = Opts::parse();
-    let canvas = opts.input.read()?;
+    let mut options = config::load_options(opts.config.as_deref())?;
+    if let Some(geometry) = opts.geometry {
+        options.window.geometry = Some(geometry);
+    }
+    if let Some(scale) = opts.window_scale {
+        options.window.geometry = None;
+        options.window.scale = Some(scale);
+    }
+
+    let mut canvas = opts.input.read()?;
+
+    if let Some(target) = opts.resize.or(opts.scale) {
+        let (width, height) = target.size(canvas.width(), canvas.height())?;
+        canvas = canvas.resized(width, height, options.resize.filter);
+    }
 
-    let options = EditorOptions::default();
     let output_canvas = edit(canvas, options);
 
     if let Some(output) = opts.output {
@@ -1,27 +1,335 @@
-use crate::canvas::{BufColor, CanvasBuffer, RgbColor};
+use crate::canvas::{BufColor, CanvasBuffer, Filter, RgbaColor};
+use crate::overlay::Overlay;
+use crate::stroke::{paint_tip, tip, tip_outline, Path, Stroke, TipShape};
+use crate::text::{draw_text, Typeface};
 use crate::util::Point;
 
-use minifb::Window;
+use minifb::{Key, KeyRepeat, Window};
+use serde::de::{self, Deserializer};
 use serde::Deserialize;
+use std::borrow::Cow;
+use std::str::FromStr;
 
 // Enter crop mode with C: select crop area with mouse
 // Enter brush mode with B: paint with mouse, resize brush with scroll, 1, 2, 3, 4, 5, 6, 7 and Ctrl+Scrollwheel to switch colors
 // Esc to enter view mode and hide all UI(exit of other modes)
 
-#[derive(Clone, Deserialize, Debug)]
+#[derive(Clone, Debug)]
 pub enum Mode {
-    Brush { last_brush_pos: Option<Point> },
-    // Eraser
-    // Text { text: String, pos: Point, size: f32 },
-    //
-    Crop { selection: Option<CropSelection> },
+    Brush {
+        path: Option<Path>,
+        /// Invert the pixels under the brush instead of painting the color.
+        inverted: bool,
+        stroke: Option<Stroke>,
+    },
+    Highlighter {
+        path: Option<Path>,
+        stroke: Option<Stroke>,
+    },
+    Eraser {
+        path: Option<Path>,
+        stroke: Option<Stroke>,
+    },
+    Text {
+        text: String,
+        pos: Option<Point>,
+    },
+    Arrow {
+        tail: Option<Point>,
+    },
+    Shape {
+        shape: Shape,
+        filled: bool,
+        start: Option<Point>,
+    },
+    Bucket,
+    Picker,
+    Redact {
+        start: Option<Point>,
+        /// The preview of the last frame, reused while the rectangle and options stay the
+        /// same, since blurring is slow.
+        cached: Option<Redacted>,
+    },
+    Select {
+        selection: Option<Selection>,
+    },
+    Crop {
+        selection: Option<CropSelection>,
+    },
     View,
 }
 
+/// Names accepted when parsing a mode, e.g. for the starting mode in the config file.
+pub const MODE_NAMES: &[&str] = &[
+    "brush",
+    "highlighter",
+    "eraser",
+    "text",
+    "arrow",
+    "rectangle",
+    "ellipse",
+    "bucket",
+    "picker",
+    "redact",
+    "select",
+    "crop",
+    "view",
+];
+
+impl FromStr for Mode {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "brush" => Ok(Mode::Brush {
+                path: None,
+                inverted: false,
+                stroke: None,
+            }),
+            "highlighter" => Ok(Mode::Highlighter {
+                path: None,
+                stroke: None,
+            }),
+            "eraser" => Ok(Mode::Eraser {
+                path: None,
+                stroke: None,
+            }),
+            "text" => Ok(Mode::Text {
+                text: String::new(),
+                pos: None,
+            }),
+            "arrow" => Ok(Mode::Arrow { tail: None }),
+            "rectangle" => Ok(Mode::Shape {
+                shape: Shape::Rectangle,
+                filled: false,
+                start: None,
+            }),
+            "ellipse" => Ok(Mode::Shape {
+                shape: Shape::Ellipse,
+                filled: false,
+                start: None,
+            }),
+            "bucket" => Ok(Mode::Bucket),
+            "picker" => Ok(Mode::Picker),
+            "redact" => Ok(Mode::Redact {
+                start: None,
+                cached: None,
+            }),
+            "select" => Ok(Mode::Select { selection: None }),
+            "crop" => Ok(Mode::Crop { selection: None }),
+            "view" => Ok(Mode::View),
+            _ => Err("no match"),
+        }
+    }
+}
+
+impl Mode {
+    /// The name of the mode, as in `MODE_NAMES`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Brush { .. } => "brush",
+            Mode::Highlighter { .. } => "highlighter",
+            Mode::Eraser { .. } => "eraser",
+            Mode::Text { .. } => "text",
+            Mode::Arrow { .. } => "arrow",
+            Mode::Shape {
+                shape: Shape::Rectangle,
+                ..
+            } => "rectangle",
+            Mode::Shape {
+                shape: Shape::Ellipse,
+                ..
+            } => "ellipse",
+            Mode::Bucket => "bucket",
+            Mode::Picker => "picker",
+            Mode::Redact { .. } => "redact",
+            Mode::Select { .. } => "select",
+            Mode::Crop { .. } => "crop",
+            Mode::View => "view",
+        }
+    }
+
+    /// Whether the mode uses the keyboard for itself, so key bindings must be ignored.
+    pub fn captures_keyboard(&self) -> bool {
+        matches!(self, Mode::Text { pos: Some(_), .. })
+    }
+
+    /// Finishes the operation in progress, like pasting floating pixels back into the image
+    /// or drawing the text being typed.
+    pub fn commit_pending(
+        &mut self,
+        brush: &Brush,
+        typeface: Option<&Typeface>,
+        selection_options: &SelectionOptions,
+        temporal_buffer: &mut CanvasBuffer,
+    ) -> ShouldSave {
+        match self {
+            Mode::Text { text, pos } => {
+                let should_save = match (pos.take(), typeface) {
+                    (Some(text_pos), Some(typeface)) if !text.is_empty() => {
+                        let color = BufColor::from(brush.color);
+                        draw_text(
+                            temporal_buffer,
+                            &typeface.font,
+                            typeface.size,
+                            text_pos,
+                            text,
+                            color,
+                        );
+                        ShouldSave::Save
+                    }
+                    _ => ShouldSave::Continue,
+                };
+                text.clear();
+                should_save
+            }
+            Mode::Select { selection } => match selection.take() {
+                Some(Selection::Floating(floating)) => {
+                    let (pixels, pos) = floating.render(selection_options.filter);
+                    temporal_buffer.blend_buffer_s(pos.0, pos.1, &pixels);
+                    ShouldSave::Save
+                }
+                _ => ShouldSave::Continue,
+            },
+            _ => ShouldSave::Continue,
+        }
+    }
+
+    /// Cancels the operation in progress, like putting floating pixels back where they were
+    /// lifted from. Returns false if there was nothing to cancel.
+    pub fn cancel_pending(&mut self, temporal_buffer: &mut CanvasBuffer) -> bool {
+        match self {
+            Mode::Select { selection } => match selection.take() {
+                Some(Selection::Floating(Floating {
+                    pixels,
+                    origin: Some(origin),
+                    ..
+                })) => {
+                    temporal_buffer.paste(origin.0 as u32, origin.1 as u32, &pixels);
+                    true
+                }
+                Some(_) => true,
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Forgets the operation in progress, keeping the settings of the mode. Anything that
+    /// should be kept must be committed first.
+    pub fn reset(&mut self) {
+        match self {
+            Mode::Brush { path, stroke, .. }
+            | Mode::Highlighter { path, stroke }
+            | Mode::Eraser { path, stroke } => {
+                *path = None;
+                *stroke = None;
+            }
+            Mode::Text { text, pos } => {
+                text.clear();
+                *pos = None;
+            }
+            Mode::Arrow { tail } => *tail = None,
+            Mode::Shape { start, .. } => *start = None,
+            Mode::Redact { start, cached } => {
+                *start = None;
+                *cached = None;
+            }
+            Mode::Select { selection } => *selection = None,
+            Mode::Crop { selection } => *selection = None,
+            Mode::Bucket | Mode::Picker | Mode::View => (),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse()
+            .map_err(|_| de::Error::unknown_variant(&name, MODE_NAMES))
+    }
+}
+
 #[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
 pub struct Brush {
     pub size: u32,
-    pub color: RgbColor,
+    pub color: RgbaColor,
+    pub tip: TipShape,
+    /// Length in pixels of the string the brush is pulled by, which steadies strokes. 0
+    /// disables it.
+    pub stabilizer: u32,
+    /// Length of arrowheads, as a multiple of the brush size.
+    pub arrowhead: u32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            size: 2,
+            color: RgbaColor([0xFF, 0x00, 0x00, 0xFF]),
+            tip: TipShape::Circle,
+            stabilizer: 0,
+            arrowhead: 5,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct BucketOptions {
+    /// Maximum difference of any color channel for a pixel to be filled.
+    pub tolerance: u8,
+    /// 4 to fill only through pixel sides, 8 to also fill through corners.
+    pub connectivity: u8,
+}
+
+impl Default for BucketOptions {
+    fn default() -> Self {
+        Self {
+            tolerance: 0,
+            connectivity: 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactStyle {
+    Pixelate,
+    Blur,
+    /// A box of the selected color.
+    Box,
+}
+
+impl RedactStyle {
+    pub fn next(self) -> Self {
+        match self {
+            RedactStyle::Pixelate => RedactStyle::Blur,
+            RedactStyle::Blur => RedactStyle::Box,
+            RedactStyle::Box => RedactStyle::Pixelate,
+        }
+    }
+}
+
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactOptions {
+    pub style: RedactStyle,
+    /// Size of the squares of pixelated regions.
+    pub block_size: u32,
+    /// How far the blur spreads each pixel.
+    pub blur_radius: u32,
+}
+
+impl Default for RedactOptions {
+    fn default() -> Self {
+        Self {
+            style: RedactStyle::Pixelate,
+            block_size: 10,
+            blur_radius: 12,
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -34,59 +342,967 @@ pub enum ShouldSave {
 pub struct Input<'w> {
     pub mouse_pos: Point,
     pub mouse_down: bool,
+    /// Whether the mouse button went down since the last frame.
+    pub mouse_pressed: bool,
     pub scroll: i32,
+    pub shift: bool,
+    pub ctrl: bool,
+    /// Characters typed since the last frame.
+    pub chars: &'w [char],
+    /// Window pixels per image pixel.
+    pub zoom: f32,
 
     pub window: &'w Window,
 }
 
+/// What a mode shows on top of the image without changing it yet.
+#[derive(Debug)]
+pub struct Preview {
+    /// A copy of the image with the work in progress drawn over it.
+    pub image: CanvasBuffer,
+    /// Marks drawn over the image once it is shown in the window.
+    pub overlays: Vec<Overlay>,
+}
+
+/// Moves the path of a stroke along with the mouse, starting it when the mouse is pressed.
+/// Returns the points to paint, and whether the stroke just ended.
+fn follow_mouse(input: &Input, path: &mut Option<Path>, stabilizer: u32) -> (Vec<Point>, bool) {
+    if input.mouse_down {
+        let path = path.get_or_insert_with(Path::default);
+        (path.push(input.mouse_pos, stabilizer), false)
+    } else {
+        match path.take() {
+            Some(mut path) => (path.finish(), true),
+            None => (Vec::new(), false),
+        }
+    }
+}
+
 pub fn brush_mode(
     input: &Input,
     brush: &mut Brush,
-    last_brush_pos: &mut Option<Point>,
+    path: &mut Option<Path>,
+    inverted: bool,
+    stroke: &mut Option<Stroke>,
+    temporal_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
+) -> ShouldSave {
+    let mut should_save = ShouldSave::Continue;
+
+    let color = BufColor::from(brush.color);
+    let paint = |_, _, pix: BufColor, coverage: f32| {
+        if inverted {
+            pix.mix(pix.inverted(), coverage)
+        } else {
+            let alpha = color.alpha() as f32 * coverage;
+            color.with_alpha(alpha.round() as u8).over(pix)
+        }
+    };
+
+    let (points, ended) = follow_mouse(input, path, brush.stabilizer);
+    if !points.is_empty() {
+        let (shape, size) = (brush.tip, brush.size as i32);
+        let stroke = stroke.get_or_insert_with(Stroke::default);
+
+        for point in points {
+            stroke.stamp(temporal_buffer, tip(shape, point, size), paint);
+        }
+    }
+    if ended {
+        *stroke = None;
+        should_save = ShouldSave::Save;
+    }
+    brush.size = (brush.size as i32 + input.scroll).clamp(2, i32::MAX) as u32;
+
+    // Brush preview, where the brush is when stabilized. Inverting the stroke being painted
+    // again would hide it.
+    if !inverted || stroke.is_none() {
+        let pos = path
+            .as_ref()
+            .and_then(Path::brush)
+            .unwrap_or(input.mouse_pos);
+        paint_tip(
+            &mut preview.image,
+            tip(brush.tip, pos, brush.size as i32),
+            paint,
+        );
+    }
+
+    should_save
+}
+
+/// Paints back the pixels of the original image. Once pixels are moved around (e.g. by a
+/// crop or a flip), the original no longer lines up with the image and is `None`, so the
+/// eraser does nothing.
+pub fn eraser_mode(
+    input: &Input,
+    brush: &mut Brush,
+    original: Option<&CanvasBuffer>,
+    path: &mut Option<Path>,
+    stroke: &mut Option<Stroke>,
     temporal_buffer: &mut CanvasBuffer,
-    composite_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
 ) -> ShouldSave {
-    let brush_pos = Some(input.mouse_pos).filter(|_| input.mouse_down);
     let mut should_save = ShouldSave::Continue;
+    brush.size = (brush.size as i32 + input.scroll).clamp(2, i32::MAX) as u32;
 
-    if *last_brush_pos != brush_pos {
-        if let Some(pos) = brush_pos {
-            let color = BufColor::from(brush.color);
-            let mut paint_pos =
-                |x, y| temporal_buffer.draw_square_s(x, y, brush.size as i32, color);
+    let original = match original {
+        Some(original) => original,
+        None => {
+            *path = None;
+            *stroke = None;
+            return should_save;
+        }
+    };
+    let restore = |x, y, pix: BufColor, coverage| pix.mix(original.get_pixel(x, y), coverage);
 
-            if let Some(last) = last_brush_pos {
-                let (pos_x, pos_y) = (pos.0 as i32, pos.1 as i32);
-                let (last_x, last_y) = (last.0 as i32, last.1 as i32);
+    let (points, ended) = follow_mouse(input, path, brush.stabilizer);
+    let (shape, size) = (brush.tip, brush.size as i32);
+    if !points.is_empty() {
+        let stroke = stroke.get_or_insert_with(Stroke::default);
 
-                let vec = ((pos_x - last_x) as f32, (pos_y - last_y) as f32);
-                let dist = (vec.0.powi(2) + vec.1.powi(2)).sqrt().abs();
-                let vec = ((vec.0 / dist), (vec.1 / dist));
+        for point in points {
+            stroke.stamp(temporal_buffer, tip(shape, point, size), restore);
+        }
+    }
+    if ended {
+        *stroke = None;
+        should_save = ShouldSave::Save;
+    }
 
-                for i in 1..=(dist as i32) {
-                    let x = (last.0 as f32 + vec.0 * i as f32) as i32;
-                    let y = (last.1 as f32 + vec.1 * i as f32) as i32;
+    // Eraser preview: what it would restore, outlined
+    let pos = path
+        .as_ref()
+        .and_then(Path::brush)
+        .unwrap_or(input.mouse_pos);
+    paint_tip(&mut preview.image, tip(shape, pos, size), restore);
+    preview.image.invert_points(&tip_outline(shape, pos, size));
 
-                    paint_pos(x, y);
-                }
-            } else {
-                paint_pos(pos.0, pos.1);
+    should_save
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HighlightBlend {
+    /// Multiply the colors of the image by the highlighter color, keeping dark text dark.
+    Multiply,
+    /// Paint the highlighter color over the image.
+    Normal,
+}
+
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct HighlighterOptions {
+    pub blend: HighlightBlend,
+    /// Opacity of the highlighter, between 0 and 1.
+    pub opacity: f32,
+    pub size: u32,
+}
+
+impl Default for HighlighterOptions {
+    fn default() -> Self {
+        Self {
+            blend: HighlightBlend::Multiply,
+            opacity: 1.0,
+            size: 16,
+        }
+    }
+}
+
+pub fn highlighter_mode(
+    input: &Input,
+    brush: &Brush,
+    options: &mut HighlighterOptions,
+    path: &mut Option<Path>,
+    stroke: &mut Option<Stroke>,
+    temporal_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
+) -> ShouldSave {
+    let mut should_save = ShouldSave::Continue;
+
+    let color = BufColor::from(brush.color);
+    let size = options.size as i32;
+    let (blend, opacity) = (options.blend, options.opacity);
+    let paint = |_, _, pix: BufColor, coverage: f32| match blend {
+        HighlightBlend::Multiply => pix.mix(pix.multiply(color), opacity * coverage),
+        HighlightBlend::Normal => {
+            let alpha = color.alpha() as f32 * opacity * coverage;
+            color.with_alpha(alpha.round() as u8).over(pix)
+        }
+    };
+
+    let (points, ended) = follow_mouse(input, path, 0);
+    if !points.is_empty() {
+        let stroke = stroke.get_or_insert_with(Stroke::default);
+
+        for point in points {
+            stroke.stamp(temporal_buffer, tip(TipShape::Square, point, size), paint);
+        }
+    }
+    if ended {
+        *stroke = None;
+        should_save = ShouldSave::Save;
+    }
+
+    // Preview the highlighter under the cursor, unless a stroke is being painted
+    if stroke.is_none() {
+        let tip = tip(TipShape::Square, input.mouse_pos, size);
+        paint_tip(&mut preview.image, tip, paint);
+    }
+
+    options.size = (size + input.scroll).clamp(2, 512) as u32;
+    should_save
+}
+
+pub fn text_mode(
+    input: &Input,
+    typeface: &mut Typeface,
+    brush: &Brush,
+    text: &mut String,
+    pos: &mut Option<Point>,
+    temporal_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
+) -> ShouldSave {
+    let mut should_save = ShouldSave::Continue;
+    let color = BufColor::from(brush.color);
+    let font = &typeface.font;
+
+    let commit = input.mouse_pressed || input.window.is_key_pressed(Key::Escape, KeyRepeat::No);
+
+    if let Some(text_pos) = *pos {
+        if commit {
+            if !text.is_empty() {
+                draw_text(temporal_buffer, font, typeface.size, text_pos, text, color);
+                should_save = ShouldSave::Save;
             }
+
+            text.clear();
+            *pos = None;
         } else {
-            should_save = ShouldSave::Save;
+            text.extend(input.chars.iter().filter(|c| !c.is_control()));
+
+            if input.window.is_key_pressed(Key::Backspace, KeyRepeat::Yes) {
+                text.pop();
+            }
+            if input.window.is_key_pressed(Key::Enter, KeyRepeat::Yes) {
+                text.push('\n');
+            }
         }
     }
-    brush.size = (brush.size as i32 + input.scroll).clamp(2, i32::MAX) as u32;
 
-    // Brush preview
-    composite_buffer.draw_square_s(
-        input.mouse_pos.0,
-        input.mouse_pos.1,
-        brush.size as i32,
-        brush.color.into(),
+    // A click places the caret, after committing any previous text
+    if input.mouse_pressed {
+        *pos = Some(input.mouse_pos);
+    }
+
+    typeface.size = (typeface.size + input.scroll as f32 * 2.0).clamp(6.0, 512.0);
+    let size = typeface.size;
+
+    // Text and caret preview
+    let (caret_pos, caret_size) = match *pos {
+        Some(text_pos) => (
+            draw_text(&mut preview.image, font, size, text_pos, text, color),
+            size,
+        ),
+        None => (input.mouse_pos, size / 2.0),
+    };
+    preview.overlays.push(Overlay::Caret {
+        pos: caret_pos,
+        height: caret_size,
+        color,
+    });
+
+    should_save
+}
+
+pub fn arrow_mode(
+    input: &Input,
+    brush: &mut Brush,
+    tail: &mut Option<Point>,
+    temporal_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
+) -> ShouldSave {
+    let mut should_save = ShouldSave::Continue;
+    let color = BufColor::from(brush.color);
+
+    brush.size = (brush.size as i32 + input.scroll).clamp(1, i32::MAX) as u32;
+
+    let center = |p: Point| (p.0 as f32 + 0.5, p.1 as f32 + 0.5);
+    let draw_arrow = |buffer: &mut CanvasBuffer, tail: Point| {
+        buffer.draw_arrow_aa(
+            center(tail),
+            center(input.mouse_pos),
+            brush.size as f32,
+            brush.size as f32 * brush.arrowhead as f32,
+            color,
+        );
+    };
+
+    if let Some(start) = *tail {
+        if input.mouse_down {
+            draw_arrow(&mut preview.image, start);
+        } else {
+            // Commit the arrow when the user releases the button
+            if start != input.mouse_pos {
+                draw_arrow(temporal_buffer, start);
+                should_save = ShouldSave::Save;
+            }
+
+            *tail = None;
+        }
+    } else if input.mouse_down {
+        *tail = Some(input.mouse_pos);
+    } else {
+        // Arrow width preview
+        let pos = center(input.mouse_pos);
+        preview
+            .image
+            .draw_line_aa(pos, pos, brush.size as f32, color);
+    }
+
+    should_save
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Shape {
+    Rectangle,
+    Ellipse,
+}
+
+/// Returns the top left and bottom right corners of a shape dragged from `start` to `end`.
+/// `square` makes both sides equally long, and `centered` puts the center of the shape at
+/// `start` instead of a corner.
+fn shape_bounds(start: Point, end: Point, square: bool, centered: bool) -> (Point, Point) {
+    let (mut dx, mut dy) = (end.0 - start.0, end.1 - start.1);
+
+    if square {
+        let side = dx.abs().max(dy.abs());
+        dx = side * if dx < 0 { -1 } else { 1 };
+        dy = side * if dy < 0 { -1 } else { 1 };
+    }
+
+    let (a, b) = if centered {
+        ((start.0 - dx, start.1 - dy), (start.0 + dx, start.1 + dy))
+    } else {
+        (start, (start.0 + dx, start.1 + dy))
+    };
+
+    ((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1)))
+}
+
+pub fn shape_mode(
+    input: &Input,
+    brush: &mut Brush,
+    shape: Shape,
+    filled: bool,
+    start: &mut Option<Point>,
+    temporal_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
+) -> ShouldSave {
+    let mut should_save = ShouldSave::Continue;
+    let color = BufColor::from(brush.color);
+
+    brush.size = (brush.size as i32 + input.scroll).clamp(1, i32::MAX) as u32;
+
+    let draw_shape = |buffer: &mut CanvasBuffer, (a, b): (Point, Point)| {
+        // Both corners are inside the shape
+        let (w, h) = (b.0 - a.0 + 1, b.1 - a.1 + 1);
+        let thickness = brush.size as i32;
+
+        match (shape, filled) {
+            (Shape::Rectangle, true) => buffer.draw_rect_s(a.0, a.1, w, h, color),
+            (Shape::Rectangle, false) => {
+                buffer.draw_rect_outline_s(a.0, a.1, w, h, thickness, color)
+            }
+            (Shape::Ellipse, _) => buffer.draw_ellipse_aa(
+                (a.0 as f32 + w as f32 / 2.0, a.1 as f32 + h as f32 / 2.0),
+                (w as f32 / 2.0, h as f32 / 2.0),
+                Some(thickness as f32).filter(|_| !filled),
+                color,
+            ),
+        }
+    };
+
+    if let Some(start_pos) = *start {
+        let bounds = shape_bounds(start_pos, input.mouse_pos, input.shift, input.ctrl);
+
+        if input.mouse_down {
+            draw_shape(&mut preview.image, bounds);
+            preview.overlays.push(Overlay::Guides(bounds.0, bounds.1));
+        } else {
+            // Commit the shape when the user releases the button
+            if bounds.0 != bounds.1 {
+                draw_shape(temporal_buffer, bounds);
+                should_save = ShouldSave::Save;
+            }
+
+            *start = None;
+        }
+    } else if input.mouse_down {
+        *start = Some(input.mouse_pos);
+    }
+
+    should_save
+}
+
+pub fn bucket_mode(
+    input: &Input,
+    brush: &Brush,
+    options: &BucketOptions,
+    temporal_buffer: &mut CanvasBuffer,
+) -> ShouldSave {
+    let (x, y) = input.mouse_pos;
+
+    if input.mouse_pressed && x >= 0 && y >= 0 && temporal_buffer.in_bounds(x as u32, y as u32) {
+        temporal_buffer.flood_fill(
+            x as u32,
+            y as u32,
+            brush.color.into(),
+            options.tolerance,
+            options.connectivity == 8,
+        );
+        ShouldSave::Save
+    } else {
+        ShouldSave::Continue
+    }
+}
+
+/// Picks the color under the cursor on click. With Shift, the color also replaces the
+/// selected color of the palette.
+pub fn picker_mode(
+    input: &Input,
+    brush: &mut Brush,
+    colors: &mut [RgbaColor],
+    selected_color: u32,
+    temporal_buffer: &CanvasBuffer,
+    preview: &mut Preview,
+) -> ShouldSave {
+    let (x, y) = input.mouse_pos;
+
+    if x >= 0 && y >= 0 && temporal_buffer.in_bounds(x as u32, y as u32) {
+        if input.mouse_pressed {
+            brush.color = temporal_buffer.get_pixel(x as u32, y as u32).into();
+
+            if input.shift {
+                colors[selected_color as usize] = brush.color;
+            }
+        }
+
+        preview.overlays.push(Overlay::Loupe(input.mouse_pos));
+    }
+
+    ShouldSave::Continue
+}
+
+/// Returns the rectangle `(x, y, w, h)` with corners `a` and `b`, both included, clamped to
+/// the bounds of the buffer. Returns `None` if it is entirely out of bounds.
+fn clamped_rect(a: Point, b: Point, buffer: &CanvasBuffer) -> Option<(u32, u32, u32, u32)> {
+    let x_min = a.0.min(b.0).max(0);
+    let y_min = a.1.min(b.1).max(0);
+    let x_max = a.0.max(b.0).min(buffer.width() as i32 - 1);
+    let y_max = a.1.max(b.1).min(buffer.height() as i32 - 1);
+
+    if x_min > x_max || y_min > y_max {
+        return None;
+    }
+
+    Some((
+        x_min as u32,
+        y_min as u32,
+        (x_max - x_min + 1) as u32,
+        (y_max - y_min + 1) as u32,
+    ))
+}
+
+fn redact(
+    buffer: &mut CanvasBuffer,
+    (x, y, w, h): (u32, u32, u32, u32),
+    options: &RedactOptions,
+    color: BufColor,
+) {
+    match options.style {
+        RedactStyle::Pixelate => buffer.pixelate(x, y, w, h, options.block_size),
+        RedactStyle::Blur => buffer.blur(x, y, w, h, options.blur_radius),
+        RedactStyle::Box => buffer.draw_rect(x, y, w, h, color),
+    }
+}
+
+/// A redacted rectangle of the image, along with the options it was redacted with.
+#[derive(Clone, Debug)]
+pub struct Redacted {
+    rect: (u32, u32, u32, u32),
+    options: RedactOptions,
+    color: BufColor,
+    pixels: CanvasBuffer,
+}
+
+impl Redacted {
+    fn new(
+        buffer: &CanvasBuffer,
+        rect: (u32, u32, u32, u32),
+        options: &RedactOptions,
+        color: BufColor,
+    ) -> Self {
+        let (x, y, w, h) = rect;
+        let mut pixels = buffer.copy_rect(x, y, w, h);
+        redact(&mut pixels, (0, 0, w, h), options, color);
+
+        Self {
+            rect,
+            options: options.clone(),
+            color,
+            pixels,
+        }
+    }
+}
+
+pub fn redact_mode(
+    input: &Input,
+    brush: &Brush,
+    options: &mut RedactOptions,
+    start: &mut Option<Point>,
+    cached: &mut Option<Redacted>,
+    temporal_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
+) -> ShouldSave {
+    let mut should_save = ShouldSave::Continue;
+    let color = BufColor::from(brush.color);
+
+    // Scroll to change the block size or the blur radius
+    match options.style {
+        RedactStyle::Pixelate => {
+            options.block_size = (options.block_size as i32 + input.scroll).clamp(2, 100) as u32;
+        }
+        RedactStyle::Blur => {
+            options.blur_radius = (options.blur_radius as i32 + input.scroll).clamp(1, 100) as u32;
+        }
+        RedactStyle::Box => (),
+    }
+
+    if let Some(a) = *start {
+        let b = input.mouse_pos;
+        let rect = clamped_rect(a, b, temporal_buffer);
+
+        // Redact the rectangle again only if it or the options changed since the last frame
+        *cached = match (rect, cached.take()) {
+            (Some(rect), Some(cached))
+                if cached.rect == rect && cached.options == *options && cached.color == color =>
+            {
+                Some(cached)
+            }
+            (Some(rect), _) => Some(Redacted::new(temporal_buffer, rect, options, color)),
+            (None, _) => None,
+        };
+
+        if input.mouse_down {
+            // Preview the redaction along with guides
+            if let Some(ref redacted) = cached {
+                let (x, y, _, _) = redacted.rect;
+                preview.image.paste(x, y, &redacted.pixels);
+            }
+            preview.overlays.push(Overlay::Guides(a, b));
+        } else {
+            // Redact when the user releases the button
+            if let Some(redacted) = cached.take().filter(|_| a != b) {
+                let (x, y, _, _) = redacted.rect;
+                temporal_buffer.paste(x, y, &redacted.pixels);
+                should_save = ShouldSave::Save;
+            }
+
+            *start = None;
+        }
+    } else if input.mouse_pressed {
+        *start = Some(input.mouse_pos);
+    }
+
+    should_save
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct SelectionOptions {
+    /// How selections are resampled when they are scaled or rotated.
+    pub filter: Filter,
+}
+
+impl Default for SelectionOptions {
+    fn default() -> Self {
+        Self {
+            filter: Filter::Bilinear,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Selection {
+    /// The user is dragging out the rectangle to select.
+    Selecting { start: Point, end: Point },
+    /// A rectangle `(x, y, w, h)` of the image, whose pixels are still in place.
+    Selected { rect: (u32, u32, u32, u32) },
+    /// Pixels lifted out of the image, which can be transformed until they are committed.
+    Floating(Floating),
+}
+
+impl Selection {
+    /// Lifts the selected pixels out of the buffer, leaving a transparent hole, so that they
+    /// can be transformed. Returns the floating pixels, or `None` while still selecting.
+    pub fn lift(&mut self, temporal_buffer: &mut CanvasBuffer) -> Option<&mut Floating> {
+        if let Selection::Selected { rect: (x, y, w, h) } = *self {
+            let pixels = temporal_buffer.copy_rect(x, y, w, h);
+            temporal_buffer.draw_rect(x, y, w, h, BufColor::TRANSPARENT);
+
+            let pos = (x as i32, y as i32);
+            *self = Selection::Floating(Floating::new(pixels, pos, Some(pos)));
+        }
+
+        match self {
+            Selection::Floating(floating) => Some(floating),
+            _ => None,
+        }
+    }
+
+    /// Returns a copy of the selected pixels as they are shown.
+    pub fn copy(&self, filter: Filter, temporal_buffer: &CanvasBuffer) -> Option<CanvasBuffer> {
+        match self {
+            Selection::Selecting { .. } => None,
+            Selection::Selected { rect: (x, y, w, h) } => {
+                Some(temporal_buffer.copy_rect(*x, *y, *w, *h))
+            }
+            Selection::Floating(floating) => Some(floating.render(filter).0.into_owned()),
+        }
+    }
+}
+
+/// Sides of the box of a floating selection that have a scale handle: -1 for the left or
+/// top side, 1 for the right or bottom side and 0 for the middle.
+const SCALE_HANDLES: [(i32, i32); 8] = [
+    (-1, -1),
+    (0, -1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+    (0, 1),
+    (-1, 1),
+    (-1, 0),
+];
+/// Distance from the top side of a floating selection to its rotate handle, in window pixels.
+const ROTATE_HANDLE_DISTANCE: f32 = 20.0;
+/// How close the cursor must be to a handle to grab it, in window pixels.
+const HANDLE_REACH: f32 = 5.0;
+/// Angle that rotations snap to while holding Shift.
+const ROTATE_SNAP: f32 = std::f32::consts::PI / 12.0;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Drag {
+    /// Moving the pixels, keeping the center at the given offset from the cursor.
+    Move((f32, f32)),
+    /// Scaling the pixels with the scale handle on the given sides.
+    Scale((i32, i32)),
+    /// Rotating the pixels, keeping the given difference between the angle of the
+    /// selection and the angle of the cursor around the center.
+    Rotate(f32),
+}
+
+#[derive(Clone, Debug)]
+pub struct Floating {
+    /// The pixels as they were lifted or pasted, before being transformed.
+    pub pixels: CanvasBuffer,
+    pub center: (f32, f32),
+    /// Size of the box the pixels are scaled to.
+    pub size: (f32, f32),
+    /// Clockwise rotation around the center, in radians.
+    pub angle: f32,
+    pub flip_h: bool,
+    pub flip_v: bool,
+    /// Where the pixels were lifted from. `None` for pasted pixels.
+    pub origin: Option<Point>,
+    pub drag: Option<Drag>,
+}
+
+impl Floating {
+    /// Creates an untransformed selection with its top left corner at `pos`.
+    pub fn new(pixels: CanvasBuffer, pos: Point, origin: Option<Point>) -> Self {
+        let size = (pixels.width() as f32, pixels.height() as f32);
+
+        Self {
+            pixels,
+            center: (pos.0 as f32 + size.0 / 2.0, pos.1 as f32 + size.1 / 2.0),
+            size,
+            angle: 0.0,
+            flip_h: false,
+            flip_v: false,
+            origin,
+            drag: None,
+        }
+    }
+
+    /// Converts a point of the image to coordinates relative to the center of the box,
+    /// undoing the rotation.
+    fn to_local(&self, p: (f32, f32)) -> (f32, f32) {
+        let (sin, cos) = self.angle.sin_cos();
+        let (dx, dy) = (p.0 - self.center.0, p.1 - self.center.1);
+        (cos * dx + sin * dy, -sin * dx + cos * dy)
+    }
+
+    /// Converts coordinates relative to the center of the box back to a point of the image.
+    fn to_image(&self, p: (f32, f32)) -> (f32, f32) {
+        let (sin, cos) = self.angle.sin_cos();
+        (
+            self.center.0 + cos * p.0 - sin * p.1,
+            self.center.1 + sin * p.0 + cos * p.1,
+        )
+    }
+
+    fn scale_handle(&self, side: (i32, i32)) -> (f32, f32) {
+        self.to_image((
+            side.0 as f32 * self.size.0 / 2.0,
+            side.1 as f32 * self.size.1 / 2.0,
+        ))
+    }
+
+    fn rotate_handle(&self, zoom: f32) -> (f32, f32) {
+        self.to_image((0.0, -self.size.1 / 2.0 - ROTATE_HANDLE_DISTANCE / zoom))
+    }
+
+    fn cursor_angle(&self, p: (f32, f32)) -> f32 {
+        (p.1 - self.center.1).atan2(p.0 - self.center.0)
+    }
+
+    /// Returns what pressing the mouse at `p` starts dragging, if anything. Handles are
+    /// reached from the same distance in the window whatever the zoom.
+    fn grab(&self, p: (f32, f32), zoom: f32) -> Option<Drag> {
+        let near =
+            |handle: (f32, f32)| (handle.0 - p.0).hypot(handle.1 - p.1) <= HANDLE_REACH / zoom;
+
+        if near(self.rotate_handle(zoom)) {
+            return Some(Drag::Rotate(self.angle - self.cursor_angle(p)));
+        }
+        if let Some(side) = SCALE_HANDLES
+            .iter()
+            .find(|side| near(self.scale_handle(**side)))
+        {
+            return Some(Drag::Scale(*side));
+        }
+
+        let (x, y) = self.to_local(p);
+        if x.abs() <= self.size.0 / 2.0 && y.abs() <= self.size.1 / 2.0 {
+            Some(Drag::Move((self.center.0 - p.0, self.center.1 - p.1)))
+        } else {
+            None
+        }
+    }
+
+    /// Moves the scale handle on the given sides to `p`, keeping the opposite side in place.
+    /// Corner handles can keep the aspect ratio of the pixels.
+    fn scale_to(&mut self, side: (i32, i32), p: (f32, f32), keep_aspect: bool) {
+        let (sx, sy) = (side.0 as f32, side.1 as f32);
+        let anchor = (-sx * self.size.0 / 2.0, -sy * self.size.1 / 2.0);
+        let local = self.to_local(p);
+        let mut size = self.size;
+
+        if side.0 != 0 {
+            size.0 = ((local.0 - anchor.0) * sx).max(1.0);
+        }
+        if side.1 != 0 {
+            size.1 = ((local.1 - anchor.1) * sy).max(1.0);
+        }
+        if keep_aspect && side.0 != 0 && side.1 != 0 {
+            let (w, h) = (self.pixels.width() as f32, self.pixels.height() as f32);
+            let factor = (size.0 / w).max(size.1 / h);
+            size = ((w * factor).max(1.0), (h * factor).max(1.0));
+        }
+
+        self.center = self.to_image((anchor.0 + sx * size.0 / 2.0, anchor.1 + sy * size.1 / 2.0));
+        self.size = size;
+    }
+
+    /// Rotates the selection clockwise by `angle` radians around its center.
+    pub fn rotate(&mut self, angle: f32) {
+        self.angle += angle;
+    }
+
+    /// Mirrors the selection as it is shown, along its vertical axis.
+    pub fn flip_horizontally(&mut self) {
+        self.flip_h = !self.flip_h;
+        self.angle = -self.angle;
+    }
+
+    /// Mirrors the selection as it is shown, along its horizontal axis.
+    pub fn flip_vertically(&mut self) {
+        self.flip_v = !self.flip_v;
+        self.angle = -self.angle;
+    }
+
+    /// Returns the transformed pixels and the position of their top left corner.
+    pub fn render(&self, filter: Filter) -> (Cow<'_, CanvasBuffer>, Point) {
+        let size = (
+            self.size.0.round().max(1.0) as u32,
+            self.size.1.round().max(1.0) as u32,
+        );
+        let scaled = size != (self.pixels.width(), self.pixels.height());
+
+        let pixels = if scaled || self.angle != 0.0 || self.flip_h || self.flip_v {
+            let mut pixels = self.pixels.clone();
+
+            if self.flip_h {
+                pixels.flip_horizontal();
+            }
+            if self.flip_v {
+                pixels.flip_vertical();
+            }
+            if scaled {
+                pixels = pixels.resized(size.0, size.1, filter);
+            }
+            if self.angle != 0.0 {
+                pixels = pixels.rotated(self.angle, filter);
+            }
+
+            Cow::Owned(pixels)
+        } else {
+            Cow::Borrowed(&self.pixels)
+        };
+
+        let pos = (
+            (self.center.0 - pixels.width() as f32 / 2.0).round() as i32,
+            (self.center.1 - pixels.height() as f32 / 2.0).round() as i32,
+        );
+
+        (pixels, pos)
+    }
+
+    /// Adds the outline of the box along with its handles to the overlays.
+    fn draw_frame(&self, zoom: f32, overlays: &mut Vec<Overlay>) {
+        let (hw, hh) = (self.size.0 / 2.0, self.size.1 / 2.0);
+        let corners = [(-hw, -hh), (hw, -hh), (hw, hh), (-hw, hh)].map(|c| self.to_image(c));
+        overlays.push(Overlay::Outline(corners));
+
+        let rotate_handle = self.rotate_handle(zoom);
+        overlays.push(Overlay::Line(self.scale_handle((0, -1)), rotate_handle));
+        overlays.push(Overlay::Handle {
+            center: rotate_handle,
+            round: true,
+        });
+
+        for side in &SCALE_HANDLES {
+            overlays.push(Overlay::Handle {
+                center: self.scale_handle(*side),
+                round: false,
+            });
+        }
+    }
+}
+
+pub fn select_mode(
+    input: &Input,
+    options: &SelectionOptions,
+    selection: &mut Option<Selection>,
+    temporal_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
+) -> ShouldSave {
+    let mut should_save = ShouldSave::Continue;
+    let mouse = input.mouse_pos;
+
+    // Arrow keys nudge the pixels, 10 at a time with Shift
+    let step = if input.shift { 10.0 } else { 1.0 };
+    let pressed = |key| input.window.is_key_pressed(key, KeyRepeat::Yes);
+    let nudge = (
+        step * (pressed(Key::Right) as i32 - pressed(Key::Left) as i32) as f32,
+        step * (pressed(Key::Down) as i32 - pressed(Key::Up) as i32) as f32,
     );
 
-    *last_brush_pos = brush_pos;
+    // Pressing on the selected pixels or nudging them lifts them out of the image, and
+    // pressing anywhere else starts a new selection
+    if let Some(Selection::Selected { rect: (x, y, w, h) }) = *selection {
+        let inside = mouse.0 >= x as i32
+            && mouse.1 >= y as i32
+            && mouse.0 < (x + w) as i32
+            && mouse.1 < (y + h) as i32;
+
+        if (input.mouse_pressed && inside) || nudge != (0.0, 0.0) {
+            if let Some(selected) = selection {
+                selected.lift(temporal_buffer);
+            }
+        } else if input.mouse_pressed {
+            *selection = None;
+        }
+    }
+
+    match selection {
+        Some(Selection::Selecting { start, end }) => {
+            if input.mouse_down {
+                *end = mouse;
+                preview.overlays.push(Overlay::Guides(*start, *end));
+            } else if start == end {
+                // A plain click selects nothing
+                *selection = None;
+            } else {
+                *selection = clamped_rect(*start, *end, temporal_buffer)
+                    .map(|rect| Selection::Selected { rect });
+            }
+        }
+        Some(Selection::Selected { rect: (x, y, w, h) }) => {
+            let (x, y) = (*x as f32, *y as f32);
+            let (x_max, y_max) = (x + *w as f32, y + *h as f32);
+            preview.overlays.push(Overlay::Outline([
+                (x, y),
+                (x_max, y),
+                (x_max, y_max),
+                (x, y_max),
+            ]));
+        }
+        Some(Selection::Floating(floating)) => {
+            let cursor = (mouse.0 as f32, mouse.1 as f32);
+
+            if input.mouse_pressed {
+                floating.drag = floating.grab(cursor, input.zoom);
+            } else if !input.mouse_down {
+                floating.drag = None;
+            }
+
+            match floating.drag {
+                Some(Drag::Move(offset)) => {
+                    floating.center = (cursor.0 + offset.0, cursor.1 + offset.1);
+                }
+                Some(Drag::Scale(side)) => floating.scale_to(side, cursor, input.shift),
+                Some(Drag::Rotate(offset)) => {
+                    let angle = floating.cursor_angle(cursor) + offset;
+
+                    floating.angle = if input.shift {
+                        (angle / ROTATE_SNAP).round() * ROTATE_SNAP
+                    } else {
+                        angle
+                    };
+                }
+                None => (),
+            }
+
+            floating.center.0 += nudge.0;
+            floating.center.1 += nudge.1;
+
+            let (pixels, pos) = floating.render(options.filter);
+            preview.image.blend_buffer_s(pos.0, pos.1, &pixels);
+            floating.draw_frame(input.zoom, &mut preview.overlays);
+
+            // Clicking away from the selection and its handles or pressing Enter commits it
+            let commit = (input.mouse_pressed && floating.drag.is_none())
+                || input.window.is_key_pressed(Key::Enter, KeyRepeat::No);
+
+            if commit {
+                temporal_buffer.blend_buffer_s(pos.0, pos.1, &pixels);
+                should_save = ShouldSave::Save;
+                *selection = None;
+            }
+        }
+        None => (),
+    }
+
+    if selection.is_none() && input.mouse_pressed {
+        *selection = Some(Selection::Selecting {
+            start: mouse,
+            end: mouse,
+        });
+    }
+
     should_save
 }
 
@@ -100,7 +1316,7 @@ pub fn crop_mode(
     input: &Input,
     selection: &mut Option<CropSelection>,
     temporal_buffer: &mut CanvasBuffer,
-    composite_buffer: &mut CanvasBuffer,
+    preview: &mut Preview,
 ) -> ShouldSave {
     let mut should_save = ShouldSave::Continue;
     let select_pos = Some(input.mouse_pos).filter(|_| input.mouse_down);
@@ -110,7 +1326,7 @@ pub fn crop_mode(
             *selection = Some(CropSelection { start, end });
 
             // Display crop guides
-            composite_buffer.draw_guides(start, end);
+            preview.overlays.push(Overlay::Guides(start, end));
         } else {
             // Do the crop when the user releases the button
             let x = start.0.min(end.0);
@@ -136,3 +1352,68 @@ pub fn crop_mode(
 
     should_save
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::text::load_font;
+
+    #[test]
+    fn cached_redaction_matches_redacting_in_place() {
+        let data = (0..24 * 16u32)
+            .map(|i| BufColor(0xFF00_0000 | i.wrapping_mul(0x0093_A5C7) & 0x00FF_FFFF))
+            .collect();
+        let image = CanvasBuffer::new(data, 24, 16);
+        let rect = (5, 3, 13, 9);
+
+        for style in [RedactStyle::Pixelate, RedactStyle::Blur, RedactStyle::Box] {
+            let options = RedactOptions {
+                style,
+                block_size: 4,
+                blur_radius: 3,
+            };
+            let mut expected = image.clone();
+            redact(&mut expected, rect, &options, BufColor::BLACK);
+
+            let redacted = Redacted::new(&image, rect, &options, BufColor::BLACK);
+            let mut pasted = image.clone();
+            pasted.paste(rect.0, rect.1, &redacted.pixels);
+            assert_eq!(pasted.data(), expected.data(), "{:?}", style);
+        }
+    }
+
+    #[test]
+    fn switching_modes_keeps_typed_text() {
+        // Text needs a system font, which not every machine running the tests has
+        let font = match load_font(None) {
+            Ok(font) => font,
+            Err(_) => return,
+        };
+        let typeface = Typeface { font, size: 24.0 };
+        let blank = CanvasBuffer::new(vec![BufColor::TRANSPARENT; 64 * 64], 64, 64);
+        let mut buffer = blank.clone();
+        let mut mode = Mode::Text {
+            text: "Hi".to_string(),
+            pos: Some((4, 4)),
+        };
+
+        let should_save = mode.commit_pending(
+            &Brush::default(),
+            Some(&typeface),
+            &SelectionOptions::default(),
+            &mut buffer,
+        );
+        assert_eq!(should_save, ShouldSave::Save);
+        assert_ne!(buffer.data(), blank.data());
+        assert!(matches!(mode, Mode::Text { ref text, pos: None } if text.is_empty()));
+
+        // Once committed, there is nothing left to draw again
+        let should_save = mode.commit_pending(
+            &Brush::default(),
+            Some(&typeface),
+            &SelectionOptions::default(),
+            &mut buffer,
+        );
+        assert_eq!(should_save, ShouldSave::Continue);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::canvas::{BufColor, CanvasBuffer};
+use crate::util::Point;
+use crate::viewport::Viewport;
+
+/// Guides, handles and other marks of a mode that are drawn over the image once it is shown
+/// in the window, so that they keep the same size whatever the zoom. Positions are in image
+/// coordinates, where `(x, y)` is the top left corner of the pixel at `(x, y)`.
+#[derive(Clone, Debug)]
+pub enum Overlay {
+    /// Lines across the image along the top and left sides of the pixels at both points.
+    Guides(Point, Point),
+    /// Outline of the quadrilateral with the given corners.
+    Outline([(f32, f32); 4]),
+    Line((f32, f32), (f32, f32)),
+    /// A handle that can be dragged with the mouse, either square or round.
+    Handle {
+        center: (f32, f32),
+        round: bool,
+    },
+    /// Magnified view of the pixels around the given one.
+    Loupe(Point),
+    /// Text caret with its top at `pos`, as tall as the given number of image pixels.
+    Caret {
+        pos: Point,
+        height: f32,
+        color: BufColor,
+    },
+}
+
+impl Overlay {
+    /// Draws the overlay onto `frame`, which shows `image` through the viewport.
+    pub fn draw(&self, frame: &mut CanvasBuffer, viewport: &Viewport, image: &CanvasBuffer) {
+        let to_window = |p: (f32, f32)| viewport.to_window(p);
+        let corner = |p: Point| to_window((p.0 as f32, p.1 as f32));
+
+        match *self {
+            Overlay::Guides(a, b) => {
+                let (w, h) = (image.width() as i32, image.height() as i32);
+                let (top_left, bottom_right) = (corner((0, 0)), corner((w, h)));
+                let span = |from: f32, to: f32, size: u32| {
+                    (from.round().max(0.0) as i32)..(to.round().min(size as f32) as i32)
+                };
+                let mut points = Vec::new();
+
+                for y in [a.1, b.1].iter().filter(|y| **y > 0 && **y < h) {
+                    let window_y = corner((0, *y)).1.round() as i32;
+                    let columns = span(top_left.0, bottom_right.0, frame.width());
+                    points.extend(columns.map(|x| (x, window_y)));
+                }
+                for x in [a.0, b.0].iter().filter(|x| **x > 0 && **x < w) {
+                    let window_x = corner((*x, 0)).0.round() as i32;
+                    let rows = span(top_left.1, bottom_right.1, frame.height());
+                    points.extend(rows.map(|y| (window_x, y)));
+                }
+
+                // Guides cross, and on the same row or column they overlap
+                points.sort_unstable();
+                points.dedup();
+                frame.invert_points(&points);
+            }
+            Overlay::Outline(corners) => {
+                let corners = corners.map(to_window);
+                let center = (
+                    corners.iter().map(|c| c.0).sum::<f32>() / 4.0,
+                    corners.iter().map(|c| c.1).sum::<f32>() / 4.0,
+                );
+
+                // Outline the window pixels just inside the sides, not the ones around them
+                let inset = |v: f32, center: f32| {
+                    let v = if v < center { v + 0.5 } else { v - 0.5 };
+                    v.floor() as i32
+                };
+                frame.draw_selection_outline(
+                    corners.map(|c| (inset(c.0, center.0), inset(c.1, center.1))),
+                );
+            }
+            Overlay::Line(a, b) => {
+                frame.draw_line_aa(to_window(a), to_window(b), 1.0, BufColor::BLACK);
+            }
+            Overlay::Handle { center, round } => frame.draw_handle(to_window(center), round),
+            Overlay::Loupe(pixel) => {
+                let center = to_window((pixel.0 as f32 + 0.5, pixel.1 as f32 + 0.5));
+                let pos = (center.0.floor() as i32, center.1.floor() as i32);
+                frame.draw_loupe(image, pixel, pos);
+            }
+            Overlay::Caret { pos, height, color } => {
+                let top = corner(pos);
+                let height = height * viewport.zoom();
+                frame.draw_rect_s(
+                    top.0.round() as i32,
+                    top.1.round() as i32,
+                    (height / 16.0).max(1.0) as i32,
+                    height.round().max(1.0) as i32,
+                    color,
+                );
+            }
+        }
+    }
+}
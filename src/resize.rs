@@ -0,0 +1,209 @@
+use crate::canvas::Filter;
+use serde::Deserialize;
+use std::str::FromStr;
+
+/// Largest width or height an image can be resized to.
+const MAX_SIZE: u32 = 32768;
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResizeOptions {
+    /// How images are resampled when they are resized.
+    pub filter: Filter,
+}
+
+impl Default for ResizeOptions {
+    fn default() -> Self {
+        Self {
+            filter: Filter::Lanczos,
+        }
+    }
+}
+
+/// A size to resize an image to.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResizeTarget {
+    /// The largest size that fits in the given width and height while keeping the aspect
+    /// ratio. Either of them may be left out.
+    Fit(Option<u32>, Option<u32>),
+    /// Exactly the given width and height, stretching the image if needed.
+    Exact(u32, u32),
+    /// The size of the image multiplied by a factor.
+    Scale(f32),
+}
+
+impl ResizeTarget {
+    /// Returns the new size of an image of the given size.
+    pub fn size(self, width: u32, height: u32) -> Result<(u32, u32), &'static str> {
+        let (w, h) = (width as f64, height as f64);
+        let (new_w, new_h) = match self {
+            ResizeTarget::Fit(Some(fit_w), Some(fit_h)) => {
+                let factor = (fit_w as f64 / w).min(fit_h as f64 / h);
+                (w * factor, h * factor)
+            }
+            ResizeTarget::Fit(Some(fit_w), None) => (fit_w as f64, h * fit_w as f64 / w),
+            ResizeTarget::Fit(None, Some(fit_h)) => (w * fit_h as f64 / h, fit_h as f64),
+            ResizeTarget::Fit(None, None) => (w, h),
+            ResizeTarget::Exact(w, h) => (w as f64, h as f64),
+            ResizeTarget::Scale(factor) => (w * factor as f64, h * factor as f64),
+        };
+        let (new_w, new_h) = (new_w.round().max(1.0), new_h.round().max(1.0));
+
+        if new_w > MAX_SIZE as f64 || new_h > MAX_SIZE as f64 {
+            return Err("size too large");
+        }
+
+        Ok((new_w as u32, new_h as u32))
+    }
+}
+
+/// Sizes are written as `WxH` to fit the image in a box, `W`, `Wx` or `xH` to set only one
+/// side, `WxH!` to stretch the image to exactly that size, or `N%` to scale it.
+impl FromStr for ResizeTarget {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const INVALID: &str = "expected a size like 800x600, 800, x600, 800x600! or 50%";
+
+        let s = s.trim();
+        let side = |s: &str| -> Result<Option<u32>, Self::Err> {
+            match s.trim() {
+                "" => Ok(None),
+                s => match s.parse() {
+                    Ok(0) => Err("sizes must be greater than 0"),
+                    Ok(n) => Ok(Some(n)),
+                    Err(_) => Err(INVALID),
+                },
+            }
+        };
+
+        if let Some(percent) = s.strip_suffix('%') {
+            return percent
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|p| p.is_finite() && *p > 0.0)
+                .map(|p| ResizeTarget::Scale(p / 100.0))
+                .ok_or("scales must be a percentage greater than 0");
+        }
+
+        let (size, exact) = match s.strip_suffix('!') {
+            Some(size) => (size, true),
+            None => (s, false),
+        };
+        let (w, h) = match size.split_once(['x', 'X']) {
+            Some((w, h)) => (side(w)?, side(h)?),
+            None => (side(size)?, None),
+        };
+
+        match (w, h, exact) {
+            (Some(w), Some(h), true) => Ok(ResizeTarget::Exact(w, h)),
+            (_, _, true) => Err("exact sizes need both a width and a height"),
+            (None, None, false) => Err(INVALID),
+            (w, h, false) => Ok(ResizeTarget::Fit(w, h)),
+        }
+    }
+}
+
+/// Parses a scale for the command line, where the percent sign is optional.
+pub fn parse_scale(s: &str) -> Result<ResizeTarget, &'static str> {
+    format!("{}%", s.trim().trim_end_matches('%')).parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::{BufColor, CanvasBuffer};
+
+    #[test]
+    fn parses_boxes_and_sides() {
+        let fit = |w, h| Ok(ResizeTarget::Fit(w, h));
+
+        assert_eq!("800x600".parse(), fit(Some(800), Some(600)));
+        assert_eq!(" 800 X 600 ".parse(), fit(Some(800), Some(600)));
+        assert_eq!("800".parse(), fit(Some(800), None));
+        assert_eq!("800x".parse(), fit(Some(800), None));
+        assert_eq!("800 x".parse(), fit(Some(800), None));
+        assert_eq!("x600".parse(), fit(None, Some(600)));
+        assert_eq!("800x600!".parse(), Ok(ResizeTarget::Exact(800, 600)));
+    }
+
+    #[test]
+    fn parses_percentages() {
+        assert_eq!("50%".parse(), Ok(ResizeTarget::Scale(0.5)));
+        assert_eq!("250 %".parse(), Ok(ResizeTarget::Scale(2.5)));
+        assert_eq!(parse_scale("50%"), Ok(ResizeTarget::Scale(0.5)));
+        assert_eq!(parse_scale("50"), Ok(ResizeTarget::Scale(0.5)));
+        assert_eq!(parse_scale("12.5"), Ok(ResizeTarget::Scale(0.125)));
+    }
+
+    #[test]
+    fn rejects_zero() {
+        for s in ["0", "0x600", "800x0", "0x0!", "0%", "-50%"] {
+            assert!(s.parse::<ResizeTarget>().is_err(), "{}", s);
+        }
+        assert!(parse_scale("0").is_err());
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        // Factors are written as percentages, and `0.5x` would be a width of 0.5 pixels
+        for s in [
+            "",
+            "x",
+            "!",
+            "abc",
+            "800x600x2",
+            "-800",
+            "0.5x",
+            "800x600!!",
+            "50%%",
+        ] {
+            assert!(s.parse::<ResizeTarget>().is_err(), "{}", s);
+        }
+        assert!("x600!".parse::<ResizeTarget>().is_err());
+        assert!(parse_scale("0.5x").is_err());
+        assert!(parse_scale("half").is_err());
+    }
+
+    #[test]
+    fn sizes_keep_the_aspect_ratio() {
+        let size = |s: &str| s.parse::<ResizeTarget>().unwrap().size(1600, 900);
+
+        assert_eq!(size("800x600"), Ok((800, 450)));
+        assert_eq!(size("800x300"), Ok((533, 300)));
+        assert_eq!(size("800"), Ok((800, 450)));
+        assert_eq!(size("x450"), Ok((800, 450)));
+        assert_eq!(size("800x800!"), Ok((800, 800)));
+        assert_eq!(size("50%"), Ok((800, 450)));
+        assert_eq!(size("0.01%"), Ok((1, 1)));
+    }
+
+    #[test]
+    fn sizes_are_limited() {
+        let size = |s: &str| s.parse::<ResizeTarget>().unwrap().size(100, 100);
+
+        assert_eq!(size("32768x32768!"), Ok((MAX_SIZE, MAX_SIZE)));
+        assert!(size("32769x100!").is_err());
+        assert!(size("40000").is_err());
+        assert!(size("100000%").is_err());
+    }
+
+    #[test]
+    fn identity_resize_keeps_pixels() {
+        let data = (0..35u32)
+            .map(|i| {
+                let alpha = if i % 3 == 0 { 0x80 } else { 0xFF };
+                BufColor(alpha << 24 | i.wrapping_mul(0x0092_4F37) & 0x00FF_FFFF)
+            })
+            .collect::<Vec<_>>();
+        let image = CanvasBuffer::new(data, 7, 5);
+
+        for filter in [Filter::Nearest, Filter::Bilinear, Filter::Lanczos] {
+            let resized = image.resized(7, 5, filter);
+
+            assert_eq!((resized.width(), resized.height()), (7, 5));
+            assert_eq!(resized.data(), image.data(), "{:?}", filter);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use crate::canvas::{BufColor, CanvasBuffer};
+use crate::util::Point;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::iter;
+
+/// Returns the points along the segment from `from` to `to`, one pixel apart, leaving out
+/// `from`.
+pub fn segment_points(from: Point, to: Point) -> impl Iterator<Item = Point> {
+    let vec = ((to.0 - from.0) as f32, (to.1 - from.1) as f32);
+    let dist = (vec.0.powi(2) + vec.1.powi(2)).sqrt();
+    let dir = if dist > 0.0 {
+        (vec.0 / dist, vec.1 / dist)
+    } else {
+        (0.0, 0.0)
+    };
+
+    (1..=(dist as i32)).map(move |i| {
+        (
+            (from.0 as f32 + dir.0 * i as f32) as i32,
+            (from.1 as f32 + dir.1 * i as f32) as i32,
+        )
+    })
+}
+
+#[derive(Clone, Copy, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TipShape {
+    Square,
+    /// A circle with anti-aliased edges.
+    Circle,
+    /// A circle that fades out towards its edge.
+    Soft,
+}
+
+/// Returns the pixels covered by a brush tip of the given shape and size, along with how
+/// much of each one is covered (between 0 and 1).
+pub fn tip(shape: TipShape, center: Point, size: i32) -> impl Iterator<Item = (i32, i32, f32)> {
+    let half = size / 2;
+    let (x_min, y_min) = (center.0 - half, center.1 - half);
+
+    // Pixel centers are at +0.5
+    let radius = size as f32 / 2.0;
+    let (cx, cy) = (x_min as f32 + radius, y_min as f32 + radius);
+
+    (y_min..y_min + size)
+        .flat_map(move |y| (x_min..x_min + size).map(move |x| (x, y)))
+        .map(move |(x, y)| {
+            let dist = (x as f32 + 0.5 - cx).hypot(y as f32 + 0.5 - cy);
+            let coverage = match shape {
+                // Tips this small can't be round
+                _ if size <= 2 => 1.0,
+                TipShape::Square => 1.0,
+                TipShape::Circle => (radius - dist + 0.5).clamp(0.0, 1.0),
+                TipShape::Soft => {
+                    let t = (dist / radius).min(1.0);
+                    1.0 - t * t * (3.0 - 2.0 * t)
+                }
+            };
+
+            (x, y, coverage)
+        })
+        .filter(|&(_, _, coverage)| coverage > 0.0)
+}
+
+/// Replaces each pixel under the tip with `paint(x, y, pixel, coverage)`.
+pub fn paint_tip(
+    buffer: &mut CanvasBuffer,
+    tip: impl Iterator<Item = (i32, i32, f32)>,
+    paint: impl Fn(u32, u32, BufColor, f32) -> BufColor,
+) {
+    for (x, y, coverage) in tip {
+        if x >= 0 && y >= 0 && buffer.in_bounds(x as u32, y as u32) {
+            let (x, y) = (x as u32, y as u32);
+            let pix = buffer.get_pixel_mut(x, y);
+            *pix = paint(x, y, *pix, coverage);
+        }
+    }
+}
+
+/// Returns the pixels on the edge of a brush tip, to outline it with.
+pub fn tip_outline(shape: TipShape, center: Point, size: i32) -> Vec<Point> {
+    let covered: HashSet<Point> = tip(shape, center, size).map(|(x, y, _)| (x, y)).collect();
+
+    covered
+        .iter()
+        .copied()
+        .filter(|&(x, y)| {
+            [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+                .iter()
+                .any(|neighbor| !covered.contains(neighbor))
+        })
+        .collect()
+}
+
+/// A stroke being painted, which changes each pixel at most once however many times the
+/// stamps of the brush overlap. Pixels are always painted over their color from before the
+/// stroke, with the highest coverage any stamp gave them. Only the pixels under the stroke
+/// are remembered.
+#[derive(Clone, Debug, Default)]
+pub struct Stroke {
+    /// Color before the stroke and highest coverage of each pixel painted so far.
+    pixels: HashMap<(u32, u32), (BufColor, f32)>,
+}
+
+impl Stroke {
+    /// Stamps the tip on the buffer, replacing the pixels it covers more than before with
+    /// `paint(x, y, original_pixel, coverage)`.
+    pub fn stamp(
+        &mut self,
+        buffer: &mut CanvasBuffer,
+        tip: impl Iterator<Item = (i32, i32, f32)>,
+        paint: impl Fn(u32, u32, BufColor, f32) -> BufColor,
+    ) {
+        for (x, y, coverage) in tip {
+            if x < 0 || y < 0 || !buffer.in_bounds(x as u32, y as u32) {
+                continue;
+            }
+
+            let (x, y) = (x as u32, y as u32);
+            let (base, max_coverage) = self
+                .pixels
+                .entry((x, y))
+                .or_insert_with(|| (buffer.get_pixel(x, y), 0.0));
+
+            if coverage > *max_coverage {
+                *max_coverage = coverage;
+                buffer.put_pixel(x, y, paint(x, y, *base, coverage));
+            }
+        }
+    }
+}
+
+/// The path followed by a brush through the mouse positions sampled each frame, smoothed
+/// into a Catmull-Rom spline so that fast strokes don't turn into polygons.
+#[derive(Clone, Debug, Default)]
+pub struct Path {
+    /// Position of the brush, which lags behind the mouse when stabilized.
+    brush: Option<(f32, f32)>,
+    /// The last position of the mouse.
+    mouse: Point,
+    /// The last points the spline passes through.
+    points: Vec<(f32, f32)>,
+    /// Whether the segment between the last two points is still to be painted.
+    pending: bool,
+    /// The last point returned.
+    last: Point,
+}
+
+impl Path {
+    /// Position of the brush, if the path has started.
+    pub fn brush(&self) -> Option<Point> {
+        self.brush
+            .map(|(x, y)| (x.round() as i32, y.round() as i32))
+    }
+
+    /// Moves the brush towards the mouse and returns the new points to paint, one pixel
+    /// apart. With a `stabilizer`, the brush is pulled by the mouse with a string that many
+    /// pixels long, which steadies shaky hands.
+    pub fn push(&mut self, mouse: Point, stabilizer: u32) -> Vec<Point> {
+        self.mouse = mouse;
+        let mouse = (mouse.0 as f32, mouse.1 as f32);
+        let brush = match self.brush {
+            Some((x, y)) => {
+                let (dx, dy) = (mouse.0 - x, mouse.1 - y);
+                let dist = dx.hypot(dy);
+                let pull = (dist - stabilizer as f32).max(0.0);
+
+                if pull > 0.0 {
+                    (x + dx * pull / dist, y + dy * pull / dist)
+                } else {
+                    (x, y)
+                }
+            }
+            None => mouse,
+        };
+        self.brush = Some(brush);
+
+        let moved = match self.points.last() {
+            Some(&(x, y)) => (brush.0 - x).hypot(brush.1 - y) >= 1.0,
+            None => {
+                self.points.push(brush);
+                self.last = (brush.0.round() as i32, brush.1.round() as i32);
+                return vec![self.last];
+            }
+        };
+
+        // The segment leading to the brush is curved according to the next point, so it's
+        // only painted once the brush moves again, or stops
+        if !moved {
+            return self.settle();
+        }
+
+        let mut points = Vec::new();
+        if self.pending {
+            let n = self.points.len();
+            let before = self.points[n.saturating_sub(3)];
+            let (from, to) = (self.points[n - 2], self.points[n - 1]);
+            self.curve([before, from, to, brush], &mut points);
+        }
+
+        self.points.push(brush);
+        if self.points.len() > 3 {
+            self.points.remove(0);
+        }
+        self.pending = true;
+        points
+    }
+
+    /// Ends the path, returning the points left to paint for it to reach the mouse. A
+    /// stabilized brush catches up with the mouse, so that strokes end where they were
+    /// released.
+    pub fn finish(&mut self) -> Vec<Point> {
+        if self.brush.is_none() {
+            return Vec::new();
+        }
+
+        let mut points = self.push(self.mouse, 0);
+        points.extend(self.settle());
+        points
+    }
+
+    /// Returns the points left to paint for the path to reach the brush.
+    fn settle(&mut self) -> Vec<Point> {
+        let mut points = Vec::new();
+
+        if self.pending {
+            let n = self.points.len();
+            let before = self.points[n.saturating_sub(3)];
+            let (from, to) = (self.points[n - 2], self.points[n - 1]);
+            self.curve([before, from, to, to], &mut points);
+            self.pending = false;
+        }
+
+        points
+    }
+
+    /// Adds the points along the curve from `controls[1]` to `controls[2]` to `points`.
+    fn curve(&mut self, controls: [(f32, f32); 4], points: &mut Vec<Point>) {
+        let [p0, p1, p2, p3] = controls;
+        let steps = (p2.0 - p1.0).hypot(p2.1 - p1.1).ceil().max(1.0) as i32;
+
+        for step in 1..=steps {
+            let t = step as f32 / steps as f32;
+            let (t2, t3) = (t * t, t * t * t);
+            let catmull_rom = |a: f32, b: f32, c: f32, d: f32| {
+                0.5 * (2.0 * b
+                    + (c - a) * t
+                    + (2.0 * a - 5.0 * b + 4.0 * c - d) * t2
+                    + (3.0 * b - a - 3.0 * c + d) * t3)
+            };
+
+            let x = catmull_rom(p0.0, p1.0, p2.0, p3.0).round() as i32;
+            let y = catmull_rom(p0.1, p1.1, p2.1, p3.1).round() as i32;
+
+            // Fill any gap from the last point, which diagonal segments may revisit
+            for point in segment_points(self.last, (x, y)).chain(iter::once((x, y))) {
+                if point != self.last {
+                    points.push(point);
+                    self.last = point;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Follows the mouse through the given positions and ends the path.
+    fn follow(positions: &[Point], stabilizer: u32) -> Vec<Point> {
+        let mut path = Path::default();
+        let mut points: Vec<Point> = positions
+            .iter()
+            .flat_map(|pos| path.push(*pos, stabilizer))
+            .collect();
+        points.extend(path.finish());
+        points
+    }
+
+    /// Asserts that each point touches the one before it, without repeating it.
+    fn assert_contiguous(points: &[Point]) {
+        for pair in points.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let step = (b.0 - a.0).abs().max((b.1 - a.1).abs());
+            assert_eq!(step, 1, "gap or repeat between {:?} and {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn straight_strokes_stay_on_the_line() {
+        let points = follow(&[(0, 0), (7, 0), (15, 0), (22, 0), (30, 0)], 0);
+        assert_eq!(points, (0..=30).map(|x| (x, 0)).collect::<Vec<_>>());
+
+        let points = follow(&[(0, 0), (6, 6), (13, 13), (30, 30)], 0);
+        assert_eq!(points, (0..=30).map(|x| (x, x)).collect::<Vec<_>>());
+
+        // Uneven steps along a slope of 1/2
+        let points = follow(&[(0, 0), (4, 2), (18, 9), (24, 12), (40, 20)], 0);
+        assert_contiguous(&points);
+        assert_eq!(points.last(), Some(&(40, 20)));
+        for (x, y) in points {
+            assert!((y as f32 - x as f32 / 2.0).abs() <= 1.0, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn curves_pass_through_the_mouse_positions() {
+        let positions = [(0, 0), (20, 5), (30, 30), (10, 40), (0, 20)];
+        let points = follow(&positions, 0);
+
+        assert_contiguous(&points);
+        for pos in &positions {
+            assert!(points.contains(pos), "{:?} not reached", pos);
+        }
+    }
+
+    #[test]
+    fn stabilized_brush_lags_and_catches_up() {
+        let mut path = Path::default();
+
+        assert_eq!(path.push((0, 0), 10), [(0, 0)]);
+        // Within the length of the string, the brush doesn't move
+        assert!(path.push((6, 0), 10).is_empty());
+        assert_eq!(path.brush(), Some((0, 0)));
+
+        path.push((30, 0), 10);
+        assert_eq!(path.brush(), Some((20, 0)));
+
+        // Ending the stroke paints the rest of the way to the mouse
+        let points = path.finish();
+        assert_eq!(points, (1..=30).map(|x| (x, 0)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn single_click_paints_one_point() {
+        assert_eq!(follow(&[(3, 4)], 0), [(3, 4)]);
+        assert_eq!(follow(&[(3, 4), (3, 4)], 5), [(3, 4)]);
+        assert!(Path::default().finish().is_empty());
+    }
+
+    #[test]
+    fn overlapping_stamps_do_not_accumulate() {
+        let white = BufColor(0xFFFFFFFF);
+        let mut buffer = CanvasBuffer::new(vec![white; 100], 10, 10);
+        let mut stroke = Stroke::default();
+        let darken = |_, _, pix: BufColor, coverage: f32| pix.mix(BufColor::BLACK, coverage * 0.5);
+
+        stroke.stamp(&mut buffer, tip(TipShape::Soft, (5, 5), 6), darken);
+        let once = buffer.clone();
+        stroke.stamp(&mut buffer, tip(TipShape::Soft, (5, 5), 6), darken);
+        assert_eq!(buffer.data(), once.data());
+
+        // A stamp covering a pixel more than before paints it again, over its old color
+        stroke.stamp(&mut buffer, tip(TipShape::Square, (5, 5), 6), darken);
+        assert_eq!(buffer.get_pixel(5, 5), white.mix(BufColor::BLACK, 0.5));
+        assert_eq!(buffer.get_pixel(0, 0), white);
+    }
+
+    #[test]
+    fn outline_follows_the_tip() {
+        let mut outline = tip_outline(TipShape::Square, (5, 5), 3);
+        outline.sort_unstable();
+        assert_eq!(
+            outline,
+            [
+                (4, 4),
+                (4, 5),
+                (4, 6),
+                (5, 4),
+                (5, 6),
+                (6, 4),
+                (6, 5),
+                (6, 6)
+            ]
+        );
+
+        // The corners of a circle are left out
+        let outline = tip_outline(TipShape::Circle, (10, 10), 9);
+        assert!(outline.contains(&(10, 6)));
+        assert!(!outline.contains(&(6, 6)));
+    }
+}
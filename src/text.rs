@@ -0,0 +1,113 @@
+use crate::canvas::{BufColor, CanvasBuffer};
+use crate::util::Point;
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TextOptions {
+    /// Path to a TTF or OTF font. If unset, a few usual system fonts are tried.
+    pub font: Option<PathBuf>,
+    /// Initial text size in pixels.
+    pub size: f32,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self {
+            font: None,
+            size: 24.0,
+        }
+    }
+}
+
+/// The font text is typed with, and the size it is drawn at.
+pub struct Typeface {
+    pub font: FontVec,
+    /// Size in pixels.
+    pub size: f32,
+}
+
+/// Fonts tried, in order, when no font is set in the config file.
+const FALLBACK_FONTS: &[&str] = &[
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+    "/usr/share/fonts/liberation/LiberationSans-Regular.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/System/Library/Fonts/Supplemental/Arial.ttf",
+    "C:\\Windows\\Fonts\\arial.ttf",
+];
+
+/// Loads the font at the given path, or the first fallback font found if none is given.
+pub fn load_font(path: Option<&Path>) -> Result<FontVec, String> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => FALLBACK_FONTS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.exists())
+            .ok_or(
+                "no font found, set one with `font` in the `[text]` section of the config file",
+            )?,
+    };
+
+    let data =
+        fs::read(&path).map_err(|e| format!("could not read font {}: {}", path.display(), e))?;
+    FontVec::try_from_vec(data).map_err(|e| format!("invalid font {}: {}", path.display(), e))
+}
+
+/// Draws the text with its top left corner at `pos`. Lines are separated by `'\n'`.
+/// Returns the position of the top of the caret, after the last character.
+pub fn draw_text(
+    buffer: &mut CanvasBuffer,
+    font: &FontVec,
+    size: f32,
+    pos: Point,
+    text: &str,
+    color: BufColor,
+) -> Point {
+    let font = font.as_scaled(PxScale::from(size));
+    let line_height = font.height() + font.line_gap();
+
+    let mut caret = point(pos.0 as f32, pos.1 as f32);
+    let mut last_glyph = None;
+
+    for c in text.chars() {
+        if c == '\n' {
+            caret.x = pos.0 as f32;
+            caret.y += line_height;
+            last_glyph = None;
+            continue;
+        }
+
+        let id = font.glyph_id(c);
+        if let Some(last) = last_glyph {
+            caret.x += font.kern(last, id);
+        }
+
+        let glyph =
+            id.with_scale_and_position(font.scale(), point(caret.x, caret.y + font.ascent()));
+        caret.x += font.h_advance(id);
+        last_glyph = Some(id);
+
+        if let Some(outline) = font.outline_glyph(glyph) {
+            let bounds = outline.px_bounds();
+
+            outline.draw(|x, y, coverage| {
+                buffer.blend_pixel_s(
+                    bounds.min.x as i32 + x as i32,
+                    bounds.min.y as i32 + y as i32,
+                    color,
+                    coverage,
+                )
+            });
+        }
+    }
+
+    (caret.x as i32, caret.y as i32)
+}
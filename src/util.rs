@@ -1,7 +1,99 @@
-use minifb::{CursorStyle, Scale, ScaleMode, Window, WindowOptions};
+use minifb::{CursorStyle, InputCallback, Scale, ScaleMode, Window, WindowOptions};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 pub type Point = (i32, i32);
 
+/// Collects the characters typed in the window, to be taken each frame.
+pub struct CharQueue(pub Rc<RefCell<Vec<char>>>);
+
+impl InputCallback for CharQueue {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(c) = std::char::from_u32(uni_char) {
+            self.0.borrow_mut().push(c);
+        }
+    }
+}
+
+/// Largest size of a window, whatever the sizing options.
+const MAX_WINDOW_SIZE: u32 = 16384;
+
+/// How large the window opened for an image is.
+#[derive(Clone, Copy, Deserialize, PartialEq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSizing {
+    /// Exact size of the window, overriding the other options.
+    pub geometry: Option<(u32, u32)>,
+    /// Window pixels per image pixel, overriding `max_size` and `min_size`.
+    pub scale: Option<f32>,
+    /// Images larger than this are scaled down to fit in it.
+    pub max_size: (u32, u32),
+    /// Images smaller than this in both dimensions are scaled up by a whole factor, until
+    /// one of them reaches it.
+    pub min_size: (u32, u32),
+}
+
+impl Default for WindowSizing {
+    fn default() -> Self {
+        Self {
+            geometry: None,
+            scale: None,
+            max_size: (1600, 900),
+            min_size: (256, 256),
+        }
+    }
+}
+
+impl WindowSizing {
+    /// Returns the size of the window to show an image of the given size in.
+    pub fn window_size(&self, image: (u32, u32)) -> (u32, u32) {
+        if let Some((width, height)) = self.geometry {
+            return (width.min(MAX_WINDOW_SIZE), height.min(MAX_WINDOW_SIZE));
+        }
+
+        let (width, height) = (image.0 as f32, image.1 as f32);
+        let (max_width, max_height) = (self.max_size.0 as f32, self.max_size.1 as f32);
+
+        let scale = if let Some(scale) = self.scale {
+            scale
+        } else if width > max_width || height > max_height {
+            (max_width / width).min(max_height / height)
+        } else {
+            // Whole factors keep every pixel the same size
+            let to_min = (self.min_size.0 as f32 / width).min(self.min_size.1 as f32 / height);
+            let to_max = (max_width / width).min(max_height / height);
+            to_min.ceil().min(to_max.floor()).max(1.0)
+        };
+
+        let size = |side: f32| ((side * scale).round() as u32).clamp(1, MAX_WINDOW_SIZE);
+        (size(width), size(height))
+    }
+}
+
+/// Parses a window size like 800x600 for the command line.
+pub fn parse_geometry(s: &str) -> Result<(u32, u32), &'static str> {
+    const INVALID: &str = "expected a size like 800x600";
+
+    let (width, height) = s.trim().split_once(['x', 'X']).ok_or(INVALID)?;
+    let side = |s: &str| match s.trim().parse() {
+        Ok(0) => Err("sizes must be greater than 0"),
+        Ok(n) => Ok(n),
+        Err(_) => Err(INVALID),
+    };
+
+    Ok((side(width)?, side(height)?))
+}
+
+/// Parses the number of window pixels per image pixel for the command line.
+pub fn parse_window_scale(s: &str) -> Result<f32, &'static str> {
+    s.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|scale| scale.is_finite() && *scale > 0.0)
+        .ok_or("the window scale must be a number greater than 0")
+}
+
 pub fn create_window(width: usize, height: usize) -> Window {
     let mut window = Window::new(
         "canved",
@@ -9,7 +101,8 @@ pub fn create_window(width: usize, height: usize) -> Window {
         height,
         WindowOptions {
             scale: Scale::X1,
-            scale_mode: ScaleMode::AspectRatioStretch,
+            scale_mode: ScaleMode::UpperLeft,
+            resize: true,
             ..Default::default()
         },
     )
@@ -17,42 +110,92 @@ pub fn create_window(width: usize, height: usize) -> Window {
         panic!("{}", e);
     });
 
-    // NOTE: Limit is removed because it causes choppy brush strokes.
-    // This can be fixed with proper velocity-based brush smoothing
-    // window.limit_update_rate(Some(std::time::Duration::from_micros(16_600)));
+    window.limit_update_rate(Some(std::time::Duration::from_micros(16_600)));
     window.set_cursor_style(CursorStyle::Crosshair);
     window
 }
 
-pub fn window_point_to_buffer_point(
-    x: i32,
-    y: i32,
-    ww: i32,
-    wh: i32,
-    bw: i32,
-    bh: i32,
-) -> Point {
-    let mut bar_x = 0;
-    let mut bar_y = 0;
+/// Maps a point of the window to the pixel of the buffer under it, where the buffer is shown
+/// `zoom` times larger with its top left corner at `origin`.
+pub fn window_point_to_buffer_point(x: f32, y: f32, zoom: f32, origin: (f32, f32)) -> Point {
+    (
+        ((x - origin.0) / zoom).floor() as i32,
+        ((y - origin.1) / zoom).floor() as i32,
+    )
+}
 
-    let mut biw_width = ww as f32;
-    let mut biw_height = wh as f32;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let window_aspect = ww as f32 / wh as f32;
-    let buffer_aspect = bw as f32 / bh as f32;
+    #[test]
+    fn large_images_are_scaled_down_to_fit() {
+        let sizing = WindowSizing::default();
 
-    if window_aspect > buffer_aspect {
-        // picture has vertical black bars
-        biw_width = bw as f32 * wh as f32 / bh as f32;
-        bar_x = ((ww as f32 / 2.0) - (biw_width as f32 / 2.0)) as i32;
-    } else {
-        // picture has horizontal black bars
-        biw_height = bh as f32 * ww as f32 / bw as f32;
-        bar_y = ((wh as f32 / 2.0) - (biw_height as f32 / 2.0)) as i32;
+        assert_eq!(sizing.window_size((3200, 900)), (1600, 450));
+        assert_eq!(sizing.window_size((1000, 1800)), (500, 900));
+        assert_eq!(sizing.window_size((1600, 900)), (1600, 900));
+        assert_eq!(sizing.window_size((20000, 1)), (1600, 1));
     }
 
-    (
-        ((x - bar_x) as f32 * bw as f32 / biw_width) as i32,
-        ((y - bar_y) as f32 * bh as f32 / biw_height) as i32,
-    )
+    #[test]
+    fn small_images_are_scaled_up_by_whole_factors() {
+        let sizing = WindowSizing::default();
+
+        assert_eq!(sizing.window_size((1, 1)), (256, 256));
+        assert_eq!(sizing.window_size((16, 16)), (256, 256));
+        assert_eq!(sizing.window_size((100, 50)), (300, 150));
+        // Scaling up stops once one side reaches the minimum size
+        assert_eq!(sizing.window_size((1, 100)), (3, 300));
+        assert_eq!(sizing.window_size((300, 800)), (300, 800));
+
+        // And before the window would be larger than the maximum size
+        let sizing = WindowSizing {
+            min_size: (1000, 1000),
+            ..WindowSizing::default()
+        };
+        assert_eq!(sizing.window_size((100, 100)), (900, 900));
+    }
+
+    #[test]
+    fn window_scale_overrides_the_limits() {
+        let sizing = |scale| WindowSizing {
+            scale: Some(scale),
+            ..WindowSizing::default()
+        };
+
+        assert_eq!(sizing(2.0).window_size((1000, 600)), (2000, 1200));
+        assert_eq!(sizing(0.5).window_size((16, 16)), (8, 8));
+        assert_eq!(sizing(0.01).window_size((10, 10)), (1, 1));
+        assert_eq!(sizing(4.0).window_size((10000, 10)), (MAX_WINDOW_SIZE, 40));
+    }
+
+    #[test]
+    fn geometry_overrides_everything() {
+        let sizing = WindowSizing {
+            geometry: Some((1280, 720)),
+            scale: Some(3.0),
+            ..WindowSizing::default()
+        };
+
+        assert_eq!(sizing.window_size((1, 1)), (1280, 720));
+        assert_eq!(sizing.window_size((5000, 5000)), (1280, 720));
+
+        let huge = WindowSizing {
+            geometry: Some((100_000, 720)),
+            ..WindowSizing::default()
+        };
+        assert_eq!(huge.window_size((10, 10)), (MAX_WINDOW_SIZE, 720));
+    }
+
+    #[test]
+    fn geometry_parsing() {
+        assert_eq!(parse_geometry("1280x720"), Ok((1280, 720)));
+        assert!(parse_geometry("1280").is_err());
+        assert!(parse_geometry("0x720").is_err());
+        assert_eq!(parse_window_scale(" 2 "), Ok(2.0));
+        for s in ["0", "-1", "nan", "inf", "two"] {
+            assert!(parse_window_scale(s).is_err(), "{}", s);
+        }
+    }
 }
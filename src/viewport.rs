@@ -0,0 +1,204 @@
+use crate::canvas::{BufColor, CanvasBuffer};
+use crate::util::{window_point_to_buffer_point, Point};
+
+/// Zoom levels stepped through when zooming in and out, in window pixels per image pixel.
+const ZOOM_LEVELS: &[f32] = &[
+    1.0 / 16.0,
+    1.0 / 12.0,
+    1.0 / 8.0,
+    1.0 / 6.0,
+    1.0 / 4.0,
+    1.0 / 3.0,
+    1.0 / 2.0,
+    2.0 / 3.0,
+    1.0,
+    1.5,
+    2.0,
+    3.0,
+    4.0,
+    6.0,
+    8.0,
+    12.0,
+    16.0,
+    24.0,
+    32.0,
+    48.0,
+    64.0,
+];
+
+/// Color of the window around the image.
+const BACKGROUND: BufColor = BufColor(0xFF202020);
+
+/// Zoom from which a grid is drawn between the pixels of the image.
+const GRID_ZOOM: f32 = 8.0;
+/// Color of the pixel grid, blended over the image.
+const GRID_COLOR: BufColor = BufColor(0x50808080);
+
+/// The part of the image shown in the window, and how large it is shown.
+#[derive(Clone, Debug)]
+pub struct Viewport {
+    /// Window pixels per image pixel, or `None` to fit the whole image in the window.
+    zoom: Option<f32>,
+    /// Point of the image at the center of the window.
+    center: (f32, f32),
+    window: (u32, u32),
+    image: (u32, u32),
+}
+
+impl Viewport {
+    /// Creates a viewport that fits the image in the window.
+    pub fn new(window: (u32, u32), image: (u32, u32)) -> Self {
+        Self {
+            zoom: None,
+            center: (image.0 as f32 / 2.0, image.1 as f32 / 2.0),
+            window,
+            image,
+        }
+    }
+
+    /// Updates the sizes of the window and the image. When the image changes size, it is
+    /// centered in the window again.
+    pub fn resize(&mut self, window: (u32, u32), image: (u32, u32)) {
+        if image != self.image {
+            self.center = (image.0 as f32 / 2.0, image.1 as f32 / 2.0);
+        }
+        self.window = window;
+        self.image = image;
+    }
+
+    /// Window pixels per image pixel.
+    pub fn zoom(&self) -> f32 {
+        self.zoom.unwrap_or_else(|| {
+            let zoom_x = self.window.0 as f32 / self.image.0 as f32;
+            let zoom_y = self.window.1 as f32 / self.image.1 as f32;
+            zoom_x.min(zoom_y)
+        })
+    }
+
+    /// Position of the top left corner of the image in the window.
+    fn origin(&self) -> (f32, f32) {
+        let zoom = self.zoom();
+
+        (
+            (self.window.0 as f32 / 2.0 - self.center.0 * zoom).round(),
+            (self.window.1 as f32 / 2.0 - self.center.1 * zoom).round(),
+        )
+    }
+
+    /// Returns the pixel of the image under the given point of the window.
+    pub fn to_image(&self, pos: (f32, f32)) -> Point {
+        window_point_to_buffer_point(pos.0, pos.1, self.zoom(), self.origin())
+    }
+
+    /// Returns the point of the window where the given point of the image is shown.
+    pub fn to_window(&self, pos: (f32, f32)) -> (f32, f32) {
+        let (zoom, origin) = (self.zoom(), self.origin());
+        (origin.0 + pos.0 * zoom, origin.1 + pos.1 * zoom)
+    }
+
+    /// Zooms to the given level, keeping the point of the image under `anchor` (a point of
+    /// the window) in place, or fits the whole image in the window if the level is `None`.
+    pub fn zoom_to(&mut self, zoom: Option<f32>, anchor: (f32, f32)) {
+        let zoom = match zoom {
+            Some(zoom) => zoom.clamp(ZOOM_LEVELS[0], ZOOM_LEVELS[ZOOM_LEVELS.len() - 1]),
+            None => {
+                *self = Self::new(self.window, self.image);
+                return;
+            }
+        };
+
+        let (old_zoom, origin) = (self.zoom(), self.origin());
+        let point = (
+            (anchor.0 - origin.0) / old_zoom,
+            (anchor.1 - origin.1) / old_zoom,
+        );
+
+        self.zoom = Some(zoom);
+        self.center = (
+            point.0 + (self.window.0 as f32 / 2.0 - anchor.0) / zoom,
+            point.1 + (self.window.1 as f32 / 2.0 - anchor.1) / zoom,
+        );
+        self.clamp_center();
+    }
+
+    /// Zooms in to the next zoom level, or out to the previous one if `steps` is negative,
+    /// keeping the point of the image under `anchor` in place.
+    pub fn zoom_by(&mut self, steps: i32, anchor: (f32, f32)) {
+        let zoom = self.zoom();
+        let mut levels = ZOOM_LEVELS.iter().copied();
+
+        let level = if steps > 0 {
+            levels.find(|level| *level > zoom * 1.01)
+        } else {
+            levels.rev().find(|level| *level < zoom * 0.99)
+        };
+
+        if let Some(level) = level {
+            self.zoom_to(Some(level), anchor);
+        }
+    }
+
+    /// Moves the image by the given distance in window pixels.
+    pub fn pan(&mut self, delta: (f32, f32)) {
+        let zoom = self.zoom();
+
+        self.zoom = Some(zoom);
+        self.center.0 -= delta.0 / zoom;
+        self.center.1 -= delta.1 / zoom;
+        self.clamp_center();
+    }
+
+    /// Keeps the center of the window over the image, so that it can't be lost.
+    fn clamp_center(&mut self) {
+        self.center.0 = self.center.0.clamp(0.0, self.image.0 as f32);
+        self.center.1 = self.center.1.clamp(0.0, self.image.1 as f32);
+    }
+
+    /// Draws the image as seen through the viewport onto `frame`, which has the size of the
+    /// window. When zoomed in enough, a grid is drawn over it between its pixels.
+    pub fn render(&self, image: &CanvasBuffer, frame: &mut CanvasBuffer) {
+        let (zoom, origin) = (self.zoom(), self.origin());
+
+        // The pixel of the image whose column or row is shown at the given one of the frame
+        let to_image = |pos: u32, origin: f32, size: u32| {
+            let pos = ((pos as f32 + 0.5 - origin) / zoom).floor();
+            Some(pos as u32).filter(|_| pos >= 0.0 && pos < size as f32)
+        };
+        let columns: Vec<Option<u32>> = (0..frame.width())
+            .map(|x| to_image(x, origin.0, image.width()))
+            .collect();
+
+        // Whether the column or row of the frame is the first one of a pixel, other than
+        // the first pixel
+        let grid = zoom >= GRID_ZOOM;
+        let starts_pixel = |pos: Option<u32>, before: Option<u32>| match (pos, before) {
+            (Some(pos), Some(before)) => pos != before,
+            _ => false,
+        };
+
+        let mut last_row = None;
+        for y in 0..frame.height() {
+            let row = to_image(y, origin.1, image.height());
+            let grid_row = grid && starts_pixel(row, last_row);
+            last_row = row;
+
+            let mut last_column = None;
+            for (x, column) in columns.iter().enumerate() {
+                let pix = match (*column, row) {
+                    (Some(image_x), Some(image_y)) => {
+                        let pix = image.get_pixel(image_x, image_y);
+
+                        if grid_row || (grid && starts_pixel(*column, last_column)) {
+                            GRID_COLOR.over(pix)
+                        } else {
+                            pix
+                        }
+                    }
+                    _ => BACKGROUND,
+                };
+                frame.put_pixel(x as u32, y, pix);
+                last_column = *column;
+            }
+        }
+    }
+}